@@ -1,11 +1,17 @@
 use crate::utils::NullTerminated;
-use alloc::vec::Vec;
-use core::fmt;
+use alloc::{
+    format,
+    vec::Vec,
+};
+use core::{
+    fmt,
+    mem::size_of,
+};
 
 #[repr(C)]
 pub struct Auxv {
     pub typ: AuxvType,
-    pub value: u64,
+    pub value: usize,
 }
 
 impl fmt::Debug for Auxv {
@@ -16,7 +22,7 @@ impl fmt::Debug for Auxv {
 
 #[derive(Copy, Clone, PartialEq, Eq)]
 #[repr(transparent)]
-pub struct AuxvType(u64);
+pub struct AuxvType(usize);
 
 impl AuxvType {
     // Marks end of auxiliary vector list
@@ -29,6 +35,10 @@ impl AuxvType {
     pub const BASE: Self = Self(7);
     // Entry point of program
     pub const ENTRY: Self = Self(9);
+    // Hardware capabilities, which are architecture-specific
+    pub const HWCAP: Self = Self(16);
+    // More hardware capabilities
+    pub const HWCAP2: Self = Self(26);
 }
 
 impl fmt::Debug for AuxvType {
@@ -38,6 +48,8 @@ impl fmt::Debug for AuxvType {
             Self::PHNUM => "PHNUM",
             Self::BASE => "BASE",
             Self::ENTRY => "ENTRY",
+            Self::HWCAP => "HWCAP",
+            Self::HWCAP2 => "HWCAP2",
             _ => "(UNKNOWN)",
         })
     }
@@ -57,7 +69,7 @@ impl Env {
     /// # Safety
     /// Walks the stack.
     pub unsafe fn read(stack_top: *mut u8) -> Self {
-        let mut ptr: *mut u64 = stack_top as _;
+        let mut ptr: *mut usize = stack_top as _;
 
         let mut env = Self::default();
 
@@ -88,6 +100,69 @@ impl Env {
         env
     }
 
+    /// Returns the value of an environment variable, if it's set
+    pub fn var(&self, name: &str) -> Option<&'static str> {
+        self.vars.iter().find_map(|var| {
+            let (key, value) = var.split_once('=')?;
+            (key == name).then(|| value)
+        })
+    }
+
+    /// Sets an environment variable, replacing it if it's already set.
+    ///
+    /// This only changes `self`: see `to_stack` to hand the new
+    /// environment over to another program.
+    pub fn set_var(&mut self, name: &str, value: &str) {
+        // Leaked so the string (and its null terminator) outlives us, just
+        // like the ones the kernel put on the stack.
+        let var = format!("{}={}\0", name, value);
+        let var: &'static str = alloc::boxed::Box::leak(var.into_boxed_str());
+        let var = &var[..var.len() - 1];
+
+        let prefix_len = name.len() + 1;
+        match self
+            .vars
+            .iter_mut()
+            .find(|v| v.as_bytes().get(..prefix_len) == Some(&var.as_bytes()[..prefix_len]))
+        {
+            Some(existing) => *existing = var,
+            None => self.vars.push(var),
+        }
+    }
+
+    /// Returns the size, in bytes, of the block `to_stack` produces
+    pub fn stack_len(&self) -> usize {
+        // argc, args, vars and aux vectors, with their terminators
+        size_of::<usize>()
+            * (1 + (self.args.len() + 1) + (self.vars.len() + 1) + 2 * (self.vectors.len() + 1))
+    }
+
+    /// Lays out `self` the way the kernel does at the top of the stack of a
+    /// new process: argc, argv, envp, then the auxiliary vectors. The strings
+    /// themselves aren't copied, the block points to them wherever they are,
+    /// so it can be moved anywhere.
+    pub fn to_stack(&self) -> Vec<u8> {
+        let mut words: Vec<usize> = Vec::with_capacity(self.stack_len() / size_of::<usize>());
+
+        words.push(self.args.len() as _);
+        words.extend(self.args.iter().map(|arg| arg.as_ptr() as usize));
+        words.push(0);
+        words.extend(self.vars.iter().map(|var| var.as_ptr() as usize));
+        words.push(0);
+        for auxv in &self.vectors {
+            words.push(auxv.typ.0);
+            words.push(auxv.value);
+        }
+        words.extend([AuxvType::NULL.0, 0]);
+
+        words.iter().flat_map(|w| w.to_ne_bytes()).collect()
+    }
+
+    /// Returns the value of an auxiliary vector, if it's there
+    pub fn vector(&self, typ: AuxvType) -> Option<usize> {
+        self.vectors.iter().find(|v| v.typ == typ).map(|v| v.value)
+    }
+
     /// Finds an auxiliary vector by type.
     /// Panics if the auxiliary vector cannot be found.
     pub fn find_vector(&mut self, typ: AuxvType) -> &mut Auxv {
@@ -8,10 +8,18 @@ pub enum EncoreError {
     MmapFileUnaligned(u64),
     /// mmap syscall failed
     MmapFailed,
+    /// mprotect syscall failed
+    MprotectFailed,
     /// Could not open file `0`
     Open(String),
     /// Could not write to file `0`
     Write(String),
     /// Could not statfile `0`
     Stat(String),
+    /// Could not get random bytes from the kernel
+    GetRandom,
+    /// Could not set the thread pointer
+    SetThreadPointer,
+    /// Could not create memory file `0`
+    MemfdCreate(String),
 }
\ No newline at end of file
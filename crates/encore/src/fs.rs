@@ -43,6 +43,23 @@ impl File {
         )
     }
 
+    /// Creates an anonymous, memory-backed file (for writing). `name` only
+    /// shows up in `/proc/self/fd`.
+    pub fn memfd(name: &str) -> Result<Self, EncoreError> {
+        let nul_name = format!("{}\0", name);
+        let fd = unsafe {
+            syscall::memfd_create(nul_name.as_ptr(), 0)
+        };
+        if (fd.0 as i64) < 0 {
+            return Err(EncoreError::MemfdCreate(name.into()));
+        }
+
+        Ok(Self {
+            path: format!("/proc/self/fd/{}", fd.0),
+            fd,
+        })
+    }
+
     fn raw_open(path: &str, flags: OpenFlags, mode: u64) -> Result<Self, EncoreError> {
         let nul_path = format!("{}\0", path);
         let fd = unsafe {
@@ -90,6 +107,18 @@ impl File {
         self.fd
     }
 
+    /// Returns the path the file was opened with
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Gives up ownership of the file descriptor, which stays open
+    pub fn into_fd(self) -> FileDescriptor {
+        let fd = self.fd;
+        core::mem::forget(self);
+        fd
+    }
+
     /// Map this file in memory (read-only)
     pub fn map(&self) -> Result<Map<'_>, EncoreError> {
         let self_data = MmapOptions::new(self.len()?)
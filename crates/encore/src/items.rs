@@ -1,14 +1,17 @@
+#[cfg(not(feature = "std"))]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     crate::println!("{}", info);
     core::intrinsics::abort();
 }
 
+#[cfg(not(feature = "std"))]
 #[lang = "eh_personality"]
 fn eh_personality() {}
 
 extern crate rlibc;
 
+#[cfg(not(feature = "std"))]
 #[no_mangle]
 #[allow(non_snake_case)]
 unsafe extern "C" fn _Unwind_Resume() {}
@@ -20,12 +23,16 @@ unsafe extern "C" fn bcmp(s1: *const u8, s2: *const u8, n: usize) -> i32 {
     compiler_builtins::mem::bcmp(s1, s2, n)
 }
 
+#[cfg(not(feature = "std"))]
 use linked_list_allocator::LockedHeap;
+#[cfg(not(feature = "std"))]
 use crate::memmap::MmapOptions;
 
+#[cfg(not(feature = "std"))]
 #[global_allocator]
 static ALLOCATOR: LockedHeap = LockedHeap::empty();
 
+#[cfg(not(feature = "std"))]
 const HEAP_SIZE_MB: u64 = 512;
 
 /// Initialize a global allocator that only uses `mmap`, with a fixed heap size.
@@ -33,8 +40,17 @@ const HEAP_SIZE_MB: u64 = 512;
 /// # Safety
 /// Calling this too late (or not at all) and doing a heap allocation will
 /// fail. The `mmap` syscall can also fail, which would be disastrous.
+#[cfg(not(feature = "std"))]
 pub unsafe fn init_allocator() {
     let heap_size = HEAP_SIZE_MB * 1024 * 1024;
     let heap_bottom = MmapOptions::new(heap_size).map().unwrap();
     ALLOCATOR.lock().init(heap_bottom as _, heap_size as _);
-}
\ No newline at end of file
+}
+
+/// With the `std` feature, std's allocator is used and there's nothing to
+/// initialize.
+///
+/// # Safety
+/// None, this is only `unsafe` to match the `no_std` version.
+#[cfg(feature = "std")]
+pub unsafe fn init_allocator() {}
\ No newline at end of file
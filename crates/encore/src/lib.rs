@@ -3,6 +3,8 @@
 #![feature(core_intrinsics)]
 
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
 pub mod items;
 pub mod error;
@@ -11,4 +13,6 @@ pub mod syscall;
 pub mod utils;
 pub mod prelude;
 pub mod fs;
-pub mod env;
\ No newline at end of file
+pub mod env;
+pub mod random;
+pub mod thread;
\ No newline at end of file
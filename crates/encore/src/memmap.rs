@@ -88,6 +88,20 @@ impl MmapOptions {
     }
 }
 
+/// Changes the protection of the pages spanning `len` bytes from `addr`,
+/// which must be aligned to 0x1000
+pub fn protect(addr: u64, len: u64, prot: MmapProt) -> Result<(), EncoreError> {
+    if !is_aligned(addr) {
+        return Err(EncoreError::MmapMemUnaligned(addr));
+    }
+
+    let res = unsafe { syscall::mprotect(addr, len, prot) };
+    if res != 0 {
+        return Err(EncoreError::MprotectFailed);
+    }
+    Ok(())
+}
+
 fn is_aligned(x: u64) -> bool {
     x & 0xFFF == 0
 }
\ No newline at end of file
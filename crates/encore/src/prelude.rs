@@ -3,6 +3,7 @@ pub use crate::{
     items::init_allocator,
     memmap::MmapOptions,
     println,
+    eprintln,
     syscall::{
         self,
         MmapFlags,
@@ -0,0 +1,19 @@
+use crate::{
+    error::EncoreError,
+    syscall,
+};
+
+/// Fills `buf` with random bytes from the kernel's CSPRNG. This may involve
+/// multiple syscalls.
+pub fn fill(mut buf: &mut [u8]) -> Result<(), EncoreError> {
+    while !buf.is_empty() {
+        let read = unsafe {
+            syscall::getrandom(buf.as_mut_ptr(), buf.len() as u64, 0)
+        };
+        if (read as i64) < 0 {
+            return Err(EncoreError::GetRandom);
+        }
+        buf = &mut core::mem::take(&mut buf)[read as usize..];
+    }
+    Ok(())
+}
\ No newline at end of file
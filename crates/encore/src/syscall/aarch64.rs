@@ -0,0 +1,216 @@
+use super::{
+    FileDescriptor,
+    MmapFlags,
+    MmapProt,
+    OpenFlags,
+};
+use core::arch::asm;
+
+/// # Safety
+/// Calls into the Kernel
+#[inline(always)]
+pub unsafe fn write(fd: FileDescriptor, buf: *const u8, count: u64) -> u64 {
+    let syscall_num: u64 = 64;
+    let mut x0 = fd.0;
+
+    asm!(
+        "svc 0",
+        in("x8") syscall_num,
+        inout("x0") x0,
+        in("x1") buf,
+        in("x2") count,
+        options(nostack),
+    );
+    x0
+}
+
+/// # Safety
+/// Calls into the Kernel. May unmap running code.
+#[inline(always)]
+pub unsafe fn mmap(
+    addr: u64,
+    len: u64,
+    prot: MmapProt,
+    flags: MmapFlags,
+    fd: FileDescriptor,
+    off: u64,
+) -> u64 {
+    let syscall_num: u64 = 222;
+    let mut x0 = addr;
+
+    asm!(
+        "svc 0",
+        in("x8") syscall_num,
+        inout("x0") x0,
+        in("x1") len,
+        in("x2") prot.bits(),
+        in("x3") flags.bits(),
+        in("x4") fd.0,
+        in("x5") off,
+        options(nostack),
+    );
+    x0
+}
+
+#[inline(always)]
+pub fn exit(code: i32) -> ! {
+    let syscall_num: u64 = 93;
+    unsafe {
+        asm!(
+            "svc 0",
+            in("x8") syscall_num,
+            in("x0") code,
+            options(noreturn, nostack),
+        );
+    }
+}
+
+/// Makes `openat` resolve relative paths like `open` does
+const AT_FDCWD: i64 = -100;
+
+/// # Safety
+/// Calls into the Kernel
+#[inline(always)]
+pub unsafe fn open(
+    filename: *const u8,
+    flags: OpenFlags,
+    mode: u64,
+) -> FileDescriptor {
+    // there's no `open`, only `openat`
+    let syscall_num: u64 = 56;
+    let mut x0 = AT_FDCWD as u64;
+
+    asm!(
+        "svc 0",
+        in("x8") syscall_num,
+        inout("x0") x0,
+        in("x1") filename,
+        in("x2") flags.bits(),
+        in("x3") mode,
+        options(nostack),
+    );
+    FileDescriptor(x0)
+}
+
+#[repr(C)]
+pub struct Stat {
+    _unused1: [u8; 48],
+    pub size: u64,
+    _unused2: [u8; 72],
+}
+
+/// # Safety
+/// Calls into the Kernel
+pub unsafe fn fstat(fd: FileDescriptor, buf: *mut Stat) -> u64 {
+    let syscall_num: u64 = 80;
+    let mut x0 = fd.0;
+
+    asm!(
+        "svc 0",
+        in("x8") syscall_num,
+        inout("x0") x0,
+        in("x1") buf,
+        options(nostack),
+    );
+    x0
+}
+
+/// # Safety
+/// Calls into the Kernel
+#[inline(always)]
+pub unsafe fn close(fd: FileDescriptor) -> u64 {
+    let syscall_number: u64 = 57;
+    let mut x0 = fd.0;
+
+    asm!(
+        "svc 0",
+        in("x8") syscall_number,
+        inout("x0") x0,
+        options(nostack),
+    );
+    x0
+}
+
+/// # Safety
+/// Calls into the Kernel
+#[inline(always)]
+pub unsafe fn munmap<T>(addr: *const T, len: u64) -> u64 {
+    let syscall_number: u64 = 215;
+    let mut x0 = addr as u64;
+
+    asm!(
+        "svc 0",
+        in("x8") syscall_number,
+        inout("x0") x0,
+        in("x1") len,
+        options(nostack),
+    );
+    x0
+}
+
+/// # Safety
+/// Calls into the Kernel
+#[inline(always)]
+pub unsafe fn mprotect(addr: u64, len: u64, prot: MmapProt) -> u64 {
+    let syscall_number: u64 = 226;
+    let mut x0 = addr;
+
+    asm!(
+        "svc 0",
+        in("x8") syscall_number,
+        inout("x0") x0,
+        in("x1") len,
+        in("x2") prot.bits(),
+        options(nostack),
+    );
+    x0
+}
+
+/// # Safety
+/// Calls into the Kernel
+#[inline(always)]
+pub unsafe fn getrandom(buf: *mut u8, buflen: u64, flags: u64) -> u64 {
+    let syscall_number: u64 = 278;
+    let mut x0 = buf as u64;
+
+    asm!(
+        "svc 0",
+        in("x8") syscall_number,
+        inout("x0") x0,
+        in("x1") buflen,
+        in("x2") flags,
+        options(nostack),
+    );
+    x0
+}
+
+/// # Safety
+/// Calls into the Kernel
+#[inline(always)]
+pub unsafe fn memfd_create(name: *const u8, flags: u64) -> FileDescriptor {
+    let syscall_number: u64 = 279;
+    let mut x0 = name as u64;
+
+    asm!(
+        "svc 0",
+        in("x8") syscall_number,
+        inout("x0") x0,
+        in("x1") flags,
+        options(nostack),
+    );
+    FileDescriptor(x0)
+}
+
+/// # Safety
+/// Calls into the kernel.
+#[inline(always)]
+pub unsafe fn dup(fd: u64) {
+    let syscall_number: u64 = 23;
+
+    asm!(
+        "svc 0",
+        in("x8") syscall_number,
+        inout("x0") fd => _,
+        options(nostack),
+    );
+}
\ No newline at end of file
@@ -0,0 +1,53 @@
+use bitflags::*;
+
+// Syscall numbers, calling conventions and structures all depend on the
+// architecture: each module has the same functions, for its own.
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
+#[cfg(target_arch = "x86_64")]
+pub use self::x86_64::*;
+
+#[cfg(target_arch = "x86")]
+mod x86;
+#[cfg(target_arch = "x86")]
+pub use self::x86::*;
+
+#[cfg(target_arch = "aarch64")]
+mod aarch64;
+#[cfg(target_arch = "aarch64")]
+pub use self::aarch64::*;
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileDescriptor(pub u64);
+
+impl FileDescriptor {
+    pub const STDIN: Self = Self(0);
+    pub const STDOUT: Self = Self(1);
+    pub const STDERR: Self = Self(2);
+}
+
+bitflags! {
+    #[derive(Default)]
+    pub struct MmapProt: u64 {
+        const READ = 0x1;
+        const WRITE = 0x2;
+        const EXEC = 0x4;
+    }
+}
+
+bitflags! {
+    pub struct MmapFlags: u64 {
+        const PRIVATE = 0x02;
+        const FIXED = 0x10;
+        const ANONYMOUS = 0x20;
+    }
+}
+
+bitflags! {
+    pub struct OpenFlags: u64 {
+        const RDONLY = 0o0;
+        const RDWR = 0o2;
+        const CREAT = 0o100;
+        const TRUNC = 0o1000;
+    }
+}
\ No newline at end of file
@@ -0,0 +1,275 @@
+use super::{
+    FileDescriptor,
+    MmapFlags,
+    MmapProt,
+    OpenFlags,
+};
+use core::arch::asm;
+
+/// Widens what a syscall returned in `eax`. Errors (-4095 to -1) stay
+/// negative, so they can be told apart the same way as on x86_64.
+fn ret(eax: u32) -> u64 {
+    if eax > -4096i32 as u32 {
+        eax as i32 as i64 as u64
+    } else {
+        eax as u64
+    }
+}
+
+/// # Safety
+/// Calls into the Kernel
+#[inline(always)]
+pub unsafe fn write(fd: FileDescriptor, buf: *const u8, count: u64) -> u64 {
+    let syscall_num: u32 = 4;
+    let mut eax = syscall_num;
+
+    asm!(
+        "int 0x80",
+        inout("eax") eax,
+        in("ebx") fd.0 as u32,
+        in("ecx") buf,
+        in("edx") count as u32,
+        options(nostack),
+    );
+    ret(eax)
+}
+
+/// Arguments of the old `mmap` syscall, which takes them all through a
+/// pointer: `mmap2` takes six, but we can't use `esi` or `ebp`.
+#[repr(C)]
+struct MmapArgs {
+    addr: u32,
+    len: u32,
+    prot: u32,
+    flags: u32,
+    fd: u32,
+    offset: u32,
+}
+
+/// # Safety
+/// Calls into the Kernel. May unmap running code.
+#[inline(always)]
+pub unsafe fn mmap(
+    addr: u64,
+    len: u64,
+    prot: MmapProt,
+    flags: MmapFlags,
+    fd: FileDescriptor,
+    off: u64,
+) -> u64 {
+    let syscall_num: u32 = 90;
+    let mut eax = syscall_num;
+    let args = MmapArgs {
+        addr: addr as _,
+        len: len as _,
+        prot: prot.bits() as _,
+        flags: flags.bits() as _,
+        fd: fd.0 as _,
+        offset: off as _,
+    };
+
+    asm!(
+        "int 0x80",
+        inout("eax") eax,
+        in("ebx") &args,
+        options(nostack),
+    );
+    ret(eax)
+}
+
+#[inline(always)]
+pub fn exit(code: i32) -> ! {
+    let syscall_num: u32 = 1;
+    unsafe {
+        asm!(
+            "int 0x80",
+            in("eax") syscall_num,
+            in("ebx") code,
+            options(noreturn, nostack),
+        );
+    }
+}
+
+/// Lets 32-bit processes open files larger than 2GiB, which 64-bit ones
+/// always can
+const O_LARGEFILE: u64 = 0o100000;
+
+/// # Safety
+/// Calls into the Kernel
+#[inline(always)]
+pub unsafe fn open(
+    filename: *const u8,
+    flags: OpenFlags,
+    mode: u64,
+) -> FileDescriptor {
+    let syscall_num: u32 = 5;
+    let mut eax = syscall_num;
+
+    asm!(
+        "int 0x80",
+        inout("eax") eax,
+        in("ebx") filename,
+        in("ecx") (flags.bits() | O_LARGEFILE) as u32,
+        in("edx") mode as u32,
+        options(nostack),
+    );
+    FileDescriptor(ret(eax))
+}
+
+/// What `fstat64` fills in
+#[repr(C)]
+pub struct Stat {
+    _unused1: [u8; 44],
+    pub size: u64,
+    _unused2: [u8; 44],
+}
+
+/// # Safety
+/// Calls into the Kernel
+pub unsafe fn fstat(fd: FileDescriptor, buf: *mut Stat) -> u64 {
+    // fstat64, `fstat` has a 32-bit size
+    let syscall_num: u32 = 197;
+    let mut eax = syscall_num;
+
+    asm!(
+        "int 0x80",
+        inout("eax") eax,
+        in("ebx") fd.0 as u32,
+        in("ecx") buf,
+        options(nostack),
+    );
+    ret(eax)
+}
+
+/// # Safety
+/// Calls into the Kernel
+#[inline(always)]
+pub unsafe fn close(fd: FileDescriptor) -> u64 {
+    let syscall_number: u32 = 6;
+    let mut eax = syscall_number;
+
+    asm!(
+        "int 0x80",
+        inout("eax") eax,
+        in("ebx") fd.0 as u32,
+        options(nostack),
+    );
+    ret(eax)
+}
+
+/// # Safety
+/// Calls into the Kernel
+#[inline(always)]
+pub unsafe fn munmap<T>(addr: *const T, len: u64) -> u64 {
+    let syscall_number: u32 = 91;
+    let mut eax = syscall_number;
+
+    asm!(
+        "int 0x80",
+        inout("eax") eax,
+        in("ebx") addr,
+        in("ecx") len as u32,
+        options(nostack),
+    );
+    ret(eax)
+}
+
+/// # Safety
+/// Calls into the Kernel
+#[inline(always)]
+pub unsafe fn mprotect(addr: u64, len: u64, prot: MmapProt) -> u64 {
+    let syscall_number: u32 = 125;
+    let mut eax = syscall_number;
+
+    asm!(
+        "int 0x80",
+        inout("eax") eax,
+        in("ebx") addr as u32,
+        in("ecx") len as u32,
+        in("edx") prot.bits() as u32,
+        options(nostack),
+    );
+    ret(eax)
+}
+
+/// Describes a segment for `set_thread_area`
+#[repr(C)]
+pub struct UserDesc {
+    /// Which GDT entry to use, or -1 to let the kernel pick (it writes back
+    /// the one it picked)
+    pub entry_number: u32,
+    pub base_addr: u32,
+    pub limit: u32,
+    pub flags: u32,
+}
+
+impl UserDesc {
+    pub const SEG_32BIT: u32 = 0x1;
+    pub const LIMIT_IN_PAGES: u32 = 0x10;
+    pub const USEABLE: u32 = 0x40;
+}
+
+/// # Safety
+/// Calls into the Kernel
+#[inline(always)]
+pub unsafe fn set_thread_area(desc: *mut UserDesc) -> u64 {
+    let syscall_number: u32 = 243;
+    let mut eax = syscall_number;
+
+    asm!(
+        "int 0x80",
+        inout("eax") eax,
+        in("ebx") desc,
+        options(nostack),
+    );
+    ret(eax)
+}
+
+/// # Safety
+/// Calls into the Kernel
+#[inline(always)]
+pub unsafe fn getrandom(buf: *mut u8, buflen: u64, flags: u64) -> u64 {
+    let syscall_number: u32 = 355;
+    let mut eax = syscall_number;
+
+    asm!(
+        "int 0x80",
+        inout("eax") eax,
+        in("ebx") buf,
+        in("ecx") buflen as u32,
+        in("edx") flags as u32,
+        options(nostack),
+    );
+    ret(eax)
+}
+
+/// # Safety
+/// Calls into the Kernel
+#[inline(always)]
+pub unsafe fn memfd_create(name: *const u8, flags: u64) -> FileDescriptor {
+    let syscall_number: u32 = 356;
+    let mut eax = syscall_number;
+
+    asm!(
+        "int 0x80",
+        inout("eax") eax,
+        in("ebx") name,
+        in("ecx") flags as u32,
+        options(nostack),
+    );
+    FileDescriptor(ret(eax))
+}
+
+/// # Safety
+/// Calls into the kernel.
+#[inline(always)]
+pub unsafe fn dup(fd: u64) {
+    let syscall_number: u32 = 41;
+
+    asm!(
+        "int 0x80",
+        inout("eax") syscall_number => _,
+        in("ebx") fd as u32,
+        options(nostack),
+    );
+}
\ No newline at end of file
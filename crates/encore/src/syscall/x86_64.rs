@@ -1,14 +1,10 @@
+use super::{
+    FileDescriptor,
+    MmapFlags,
+    MmapProt,
+    OpenFlags,
+};
 use core::arch::asm;
-use bitflags::*;
-
-#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct FileDescriptor(pub u64);
-
-impl FileDescriptor {
-    pub const STDIN: Self = Self(0);
-    pub const STDOUT: Self = Self(1);
-    pub const STDERR: Self = Self(2);
-}
 
 /// # Safety
 /// Calls into the Kernel
@@ -29,23 +25,6 @@ pub unsafe fn write(fd: FileDescriptor, buf: *const u8, count: u64) -> u64 {
     rax
 }
 
-bitflags! {
-    #[derive(Default)]
-    pub struct MmapProt: u64 {
-        const READ = 0x1;
-        const WRITE = 0x2;
-        const EXEC = 0x4;
-    }
-}
-
-bitflags! {
-    pub struct MmapFlags: u64 {
-        const PRIVATE = 0x02;
-        const FIXED = 0x10;
-        const ANONYMOUS = 0x20;
-    }
-}
-
 /// # Safety
 /// Calls into the Kernel. May unmap running code.
 #[inline(always)]
@@ -88,15 +67,6 @@ pub fn exit(code: i32) -> ! {
     }
 }
 
-bitflags! {
-    pub struct OpenFlags: u64 {
-        const RDONLY = 0o0;
-        const RDWR = 0o2;
-        const CREAT = 0o100;
-        const TRUNC = 0o1000;
-    }
-}
-
 /// # Safety
 /// Calls into the Kernel
 #[inline(always)]
@@ -179,6 +149,80 @@ pub unsafe fn munmap<T>(addr: *const T, len: u64) -> u64 {
     rax
 }
 
+/// # Safety
+/// Calls into the Kernel
+#[inline(always)]
+pub unsafe fn mprotect(addr: u64, len: u64, prot: MmapProt) -> u64 {
+    let syscall_number: u64 = 10;
+    let mut rax = syscall_number;
+
+    asm!(
+        "syscall",
+        inout("rax") rax,
+        in("rdi") addr,
+        in("rsi") len,
+        in("rdx") prot.bits(),
+        lateout("rcx") _, lateout("r11") _,
+        options(nostack),
+    );
+    rax
+}
+
+/// # Safety
+/// Calls into the Kernel
+#[inline(always)]
+pub unsafe fn arch_prctl(code: u64, addr: u64) -> u64 {
+    let syscall_number: u64 = 158;
+    let mut rax = syscall_number;
+
+    asm!(
+        "syscall",
+        inout("rax") rax,
+        in("rdi") code,
+        in("rsi") addr,
+        lateout("rcx") _, lateout("r11") _,
+        options(nostack),
+    );
+    rax
+}
+
+/// # Safety
+/// Calls into the Kernel
+#[inline(always)]
+pub unsafe fn getrandom(buf: *mut u8, buflen: u64, flags: u64) -> u64 {
+    let syscall_number: u64 = 318;
+    let mut rax = syscall_number;
+
+    asm!(
+        "syscall",
+        inout("rax") rax,
+        in("rdi") buf,
+        in("rsi") buflen,
+        in("rdx") flags,
+        lateout("rcx") _, lateout("r11") _,
+        options(nostack),
+    );
+    rax
+}
+
+/// # Safety
+/// Calls into the Kernel
+#[inline(always)]
+pub unsafe fn memfd_create(name: *const u8, flags: u64) -> FileDescriptor {
+    let syscall_number: u64 = 319;
+    let mut rax = syscall_number;
+
+    asm!(
+        "syscall",
+        inout("rax") rax,
+        in("rdi") name,
+        in("rsi") flags,
+        lateout("rcx") _, lateout("r11") _,
+        options(nostack),
+    );
+    FileDescriptor(rax)
+}
+
 /// # Safety
 /// Calls into the kernel.
 #[inline(always)]
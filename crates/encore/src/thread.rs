@@ -0,0 +1,54 @@
+use crate::error::EncoreError;
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+use crate::syscall;
+
+/// `arch_prctl` code to set the `fs` base
+#[cfg(target_arch = "x86_64")]
+const ARCH_SET_FS: u64 = 0x1002;
+
+/// Points the `fs` register, which thread-local storage is addressed from,
+/// at `tp`
+#[cfg(target_arch = "x86_64")]
+pub fn set_thread_pointer(tp: u64) -> Result<(), EncoreError> {
+    let res = unsafe { syscall::arch_prctl(ARCH_SET_FS, tp) };
+    if res != 0 {
+        return Err(EncoreError::SetThreadPointer);
+    }
+    Ok(())
+}
+
+/// Points the `gs` segment, which thread-local storage is addressed from,
+/// at `tp`: asks the kernel for a GDT entry based there, then loads its
+/// selector
+#[cfg(target_arch = "x86")]
+pub fn set_thread_pointer(tp: u64) -> Result<(), EncoreError> {
+    let mut desc = syscall::UserDesc {
+        entry_number: u32::MAX,
+        base_addr: tp as u32,
+        limit: 0xfffff,
+        flags: syscall::UserDesc::SEG_32BIT
+            | syscall::UserDesc::LIMIT_IN_PAGES
+            | syscall::UserDesc::USEABLE,
+    };
+    let res = unsafe { syscall::set_thread_area(&mut desc) };
+    if res != 0 {
+        return Err(EncoreError::SetThreadPointer);
+    }
+
+    // GDT selector, with requested privilege level 3
+    let selector = (desc.entry_number << 3) | 3;
+    unsafe {
+        core::arch::asm!("mov gs, {0:x}", in(reg) selector, options(nostack));
+    }
+    Ok(())
+}
+
+/// Points `tpidr_el0`, which thread-local storage is addressed from, at `tp`.
+/// Userspace can set it directly, this can't fail.
+#[cfg(target_arch = "aarch64")]
+pub fn set_thread_pointer(tp: u64) -> Result<(), EncoreError> {
+    unsafe {
+        core::arch::asm!("msr tpidr_el0, {0}", in(reg) tp, options(nostack));
+    }
+    Ok(())
+}
\ No newline at end of file
@@ -10,6 +10,16 @@ macro_rules! println {
     };
 }
 
+#[macro_export]
+macro_rules! eprintln {
+    ($($arg:tt)*) => {
+        {
+            use ::core::fmt::Write;
+            ::core::writeln!($crate::utils::Stderr, $($arg)*).ok();
+        }
+    };
+}
+
 pub struct Stdout;
 
 impl fmt::Write for Stdout {
@@ -25,6 +35,21 @@ impl fmt::Write for Stdout {
     }
 }
 
+pub struct Stderr;
+
+impl fmt::Write for Stderr {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        unsafe {
+            crate::syscall::write(
+                crate::syscall::FileDescriptor::STDERR,
+                s.as_ptr(),
+                s.len() as _,
+            );
+        }
+        Ok(())
+    }
+}
+
 pub trait NullTerminated
 where
     Self: Sized,
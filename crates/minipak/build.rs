@@ -4,15 +4,34 @@ use std::{
 };
 
 fn main() {
-    println!("cargo:rustc-link-arg=-nostartfiles");
-    println!("cargo:rustc-link-arg=-nodefaultlibs");
-    println!("cargo:rustc-link-arg=-static");
+    // only for minipak itself, tests are regular executables
+    println!("cargo:rustc-link-arg-bins=-nostartfiles");
+    println!("cargo:rustc-link-arg-bins=-nodefaultlibs");
+    println!("cargo:rustc-link-arg-bins=-static");
 
-    cargo_build(&PathBuf::from("../stage1"));
-    cargo_build(&PathBuf::from("../stage2"));
+    // stage1 embeds the public key signed executables are checked against
+    println!("cargo:rerun-if-env-changed=MINIPAK_PUBLIC_KEY");
+
+    // One pair of stages per guest architecture: stripped binaries for our
+    // own target end up in `embeds`, the others in `embeds/<target>`.
+    let target = std::env::var("TARGET").unwrap();
+    // tests build guests for it, see `tests/pack.rs`
+    println!("cargo:rustc-env=MINIPAK_TARGET={}", target);
+    println!("cargo:rustc-env=MINIPAK_HOST={}", std::env::var("HOST").unwrap());
+    let mut stage_targets = vec![(target.as_str(), true)];
+    if target.starts_with("x86_64") && std::env::var_os("CARGO_FEATURE_I386").is_some() {
+        stage_targets.push((I686_TARGET, false));
+    }
+    for (target, native) in stage_targets {
+        cargo_build(&PathBuf::from("../stage1"), target, native);
+        cargo_build(&PathBuf::from("../stage2"), target, native);
+    }
 }
 
-fn cargo_build(path: &Path) {
+/// Target the stages are built for to pack 32-bit guests
+const I686_TARGET: &str = "i686-unknown-linux-gnu";
+
+fn cargo_build(path: &Path, target: &str, native: bool) {
     println!("cargo:rerun-if-changed=..");
 
     let out_dir = std::env::var("OUT_DIR").unwrap();
@@ -23,6 +42,8 @@ fn cargo_build(path: &Path) {
         .arg("--target-dir")
         .arg(&target_dir)
         .arg("--release")
+        .arg("--target")
+        .arg(target)
         .current_dir(path)
         .spawn()
         .unwrap()
@@ -40,10 +61,16 @@ fn cargo_build(path: &Path) {
 
     // Let's just assume the library has the same name as the crate
     let binary_name = format!("lib{}.so", path.file_name().unwrap().to_str().unwrap());
-    let output = Command::new("objcopy")
+    let unstripped = format!("{}/release/{}", target, binary_name);
+    let stripped = if native {
+        binary_name
+    } else {
+        format!("{}/{}", target, binary_name)
+    };
+    let output = Command::new(objcopy(target))
         .arg("--strip-all")
-        .arg(&format!("release/{}", binary_name))
-        .arg(binary_name)
+        .arg(&unstripped)
+        .arg(&stripped)
         .current_dir(&target_dir)
         .spawn()
         .unwrap()
@@ -56,4 +83,20 @@ fn cargo_build(path: &Path) {
             String::from_utf8_lossy(&output.stderr[..]),
         );
     }
+}
+
+/// Returns the `objcopy` that strips `target`'s objects: the host's, unless
+/// cross-compiling to AArch64. Set `OBJCOPY` to pick another one.
+fn objcopy(target: &str) -> String {
+    println!("cargo:rerun-if-env-changed=OBJCOPY");
+    if let Ok(objcopy) = std::env::var("OBJCOPY") {
+        return objcopy;
+    }
+
+    let host = std::env::var("HOST").unwrap();
+    if target.starts_with("aarch64") && host.starts_with("aarch64") == false {
+        "aarch64-linux-gnu-objcopy".into()
+    } else {
+        "objcopy".into()
+    }
 }
\ No newline at end of file
@@ -1,5 +1,12 @@
 use core::fmt::Display;
 use encore::prelude::*;
+use pixie::{
+    guest_name,
+    Codec,
+    FilterMode,
+    KeySource,
+    ManifestString,
+};
 
 extern crate alloc;
 use alloc::borrow::Cow;
@@ -13,29 +20,116 @@ pub struct Error {
 impl Display for Error {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         writeln!(f, "Error: {}", self.message)?;
-        writeln!(f, "Usage: {} input -o output", self.program_name)?;
+        writeln!(
+            f,
+            "Usage: {} input... -o output [--codec lz4|deflate|store] [--filter none|x86|auto] \
+             [--sign-key key]",
+            self.program_name,
+        )?;
+        writeln!(f, "       {} input... -o output --best", self.program_name)?;
+        writeln!(f, "       {} input -o output --strip [--keep-debug path]", self.program_name)?;
+        writeln!(f, "       {} input... -o output --relocate", self.program_name)?;
+        writeln!(
+            f,
+            "       {} input... -o output --embed name=path [--embed name=path]...",
+            self.program_name,
+        )?;
+        writeln!(
+            f,
+            "       {} input... -o output --encrypt-key key [--key-env VAR | --key-file path]",
+            self.program_name,
+        )?;
+        writeln!(f, "       {} --unpack packed -o output", self.program_name)?;
+        writeln!(f, "       {} info packed", self.program_name)?;
 
         Ok(())
     }
 }
 
+/// What minipak should do with its input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Compress an executable into a packed executable
+    Pack,
+    /// Recover the original executable from a packed executable
+    Unpack,
+    /// Print the layout of a packed executable
+    Info,
+}
+
 #[derive(Debug)]
 pub struct Args {
-    /// The executable to compress
-    pub input: &'static str,
-    /// Where to write the compressed executable on disk
-    pub output: &'static str,
+    /// What to do with the input
+    pub mode: Mode,
+    /// The executables to compress (or, when unpacking or inspecting, the
+    /// packed executable). Never empty.
+    pub inputs: Vec<&'static str>,
+    /// Where to write the compressed (or recovered) executable on disk.
+    /// Always set, except in `info` mode.
+    pub output: Option<&'static str>,
+    /// The codec used to compress the guest
+    pub codec: Codec,
+    /// Which filters to apply to the guest before compressing it
+    pub filter: FilterMode,
+    /// Try every codec and filter mode, and keep whichever is smallest,
+    /// instead of using `codec` and `filter`
+    pub best: bool,
+    /// Drop everything from the guests that isn't needed to run them
+    pub strip: bool,
+    /// Where to write what `strip` dropped, if anywhere
+    pub keep_debug: Option<&'static str>,
+    /// Have stage2 relocate guests that are relocatable and have no
+    /// interpreter
+    pub relocate: bool,
+    /// File holding the Ed25519 secret key (seed) to sign the packed
+    /// executable with, as hexadecimal digits
+    pub sign_key: Option<&'static str>,
+    /// How to encrypt the guest (and embedded files), if at all
+    pub encrypt: Option<EncryptArgs>,
+    /// Extra files to bundle with the guest
+    pub embeds: Vec<EmbedArgs>,
 }
 
+#[derive(Debug)]
+pub struct EncryptArgs {
+    /// File holding the key to encrypt the guest with, as hexadecimal digits
+    pub key: &'static str,
+    /// Where the packed executable looks up the key when it runs
+    pub key_source: KeySource,
+}
+
+#[derive(Debug)]
+pub struct EmbedArgs {
+    /// The name the guest knows the file by
+    pub name: &'static str,
+    /// Where to read the file from
+    pub path: &'static str,
+}
+
+/// Environment variable the key is looked up in, unless told otherwise
+const DEFAULT_KEY_VAR: &str = "MINIPAK_KEY";
+
 #[derive(Default)]
 struct ArgsRaw {
-    input: Option<&'static str>,
+    info: bool,
+    unpack: bool,
+    inputs: Vec<&'static str>,
     output: Option<&'static str>,
+    codec: Option<Codec>,
+    filter: Option<FilterMode>,
+    best: bool,
+    strip: bool,
+    keep_debug: Option<&'static str>,
+    relocate: bool,
+    sign_key: Option<&'static str>,
+    encrypt_key: Option<&'static str>,
+    key_source: Option<KeySource>,
+    embeds: Vec<EmbedArgs>,
 }
 
 impl Args {
     pub fn parse(env: &Env) -> Result<Self, Error> {
-        let mut args = env.args.iter().copied();
+        let mut args = env.args.iter().copied().peekable();
         let mut raw: ArgsRaw = Default::default();
 
         // By convention, the first argument is the program's name
@@ -46,23 +140,112 @@ impl Args {
             message,
         };
 
+        // `info` is a subcommand, it has to come first
+        if args.peek() == Some(&"info") {
+            args.next();
+            raw.info = true;
+        }
+
         while let Some(arg) = args.next() {
             if arg.starts_with('-') {
                 Self::parse_flag(arg, &mut args, &mut raw, &err)?;
                 continue;
             }
 
-            if raw.input.is_some() {
-                return Err(err("Multiple input files specified".into()));
+            raw.inputs.push(arg);
+        }
+
+        let mode = match (raw.info, raw.unpack) {
+            (true, true) => return Err(err("info cannot be combined with --unpack".into())),
+            (true, false) => Mode::Info,
+            (false, true) => Mode::Unpack,
+            (false, false) => Mode::Pack,
+        };
+
+        let output = match mode {
+            Mode::Info => {
+                if raw.output.is_some() {
+                    return Err(err("info does not write an output".into()));
+                }
+                None
+            }
+            _ => Some(raw.output.ok_or_else(|| err("Missing output".into()))?),
+        };
+
+        let encrypt = match (raw.encrypt_key, raw.key_source) {
+            (Some(key), key_source) => Some(EncryptArgs {
+                key,
+                key_source: key_source.unwrap_or_else(|| {
+                    // can't fail, the name is short
+                    KeySource::Env(ManifestString::new(DEFAULT_KEY_VAR).unwrap())
+                }),
+            }),
+            (None, Some(_)) => {
+                return Err(err("--key-env and --key-file require --encrypt-key".into()));
+            }
+            (None, None) => None,
+        };
+
+        if raw.best && (raw.codec.is_some() || raw.filter.is_some()) {
+            return Err(err("--best picks the codec and filter itself".into()));
+        }
+
+        if mode != Mode::Pack && raw.embeds.is_empty() == false {
+            return Err(err("--embed only makes sense when packing".into()));
+        }
+        if mode != Mode::Pack && raw.best {
+            return Err(err("--best only makes sense when packing".into()));
+        }
+
+        if mode != Mode::Pack && raw.strip {
+            return Err(err("--strip only makes sense when packing".into()));
+        }
+        if mode != Mode::Pack && raw.relocate {
+            return Err(err("--relocate only makes sense when packing".into()));
+        }
+
+        if raw.keep_debug.is_some() {
+            if raw.strip == false {
+                return Err(err("--keep-debug requires --strip".into()));
+            }
+            if raw.inputs.len() > 1 {
+                return Err(err("--keep-debug only works with a single input".into()));
+            }
+        }
+
+        if raw.inputs.is_empty() {
+            return Err(err("Missing input".into()));
+        }
+        match mode {
+            // Each guest is selected by its file name, see `guest_name`
+            Mode::Pack => {
+                for (i, input) in raw.inputs.iter().enumerate() {
+                    let name = guest_name(input);
+                    if raw.inputs[..i].iter().any(|other| guest_name(other) == name) {
+                        return Err(err(format!("Multiple guests named {}", name).into()));
+                    }
+                }
             }
-            else {
-                raw.input = Some(arg);
+            _ => {
+                if raw.inputs.len() > 1 {
+                    return Err(err("Multiple input files specified".into()));
+                }
             }
         }
 
         Ok(Args{
-            input: raw.input.ok_or_else(|| err("Missing input".into()))?,
-            output: raw.output.ok_or_else(|| err("Missing output".into()))?,
+            mode,
+            inputs: raw.inputs,
+            output,
+            codec: raw.codec.unwrap_or(Codec::Lz4),
+            filter: raw.filter.unwrap_or(FilterMode::Auto),
+            best: raw.best,
+            strip: raw.strip,
+            keep_debug: raw.keep_debug,
+            relocate: raw.relocate,
+            sign_key: raw.sign_key,
+            encrypt,
+            embeds: raw.embeds,
         })
     }
 
@@ -87,6 +270,144 @@ impl Args {
 
                 Ok(())
             }
+            "--codec" => {
+                let name = args
+                    .next()
+                    .ok_or_else(|| err(format!("Missing codec name after {}", flag).into()))?;
+                let codec = Codec::from_name(name).ok_or_else(|| {
+                    let names = Codec::ALL.iter().map(|c| c.name()).collect::<Vec<_>>();
+                    let names = names.join(", ");
+                    err(format!("Unknown codec {} (expected one of {})", name, names).into())
+                })?;
+
+                if raw.codec.is_some() {
+                    return Err(err("Multiple codecs specified".into()));
+                }
+                else {
+                    raw.codec = Some(codec);
+                }
+
+                Ok(())
+            }
+            "--filter" => {
+                let name = args
+                    .next()
+                    .ok_or_else(|| err(format!("Missing filter mode after {}", flag).into()))?;
+                let filter = FilterMode::from_name(name).ok_or_else(|| {
+                    let names = FilterMode::ALL.iter().map(|m| m.name()).collect::<Vec<_>>();
+                    let names = names.join(", ");
+                    err(format!("Unknown filter mode {} (expected one of {})", name, names).into())
+                })?;
+
+                if raw.filter.is_some() {
+                    return Err(err("Multiple filter modes specified".into()));
+                }
+                else {
+                    raw.filter = Some(filter);
+                }
+
+                Ok(())
+            }
+            "--best" => {
+                raw.best = true;
+                Ok(())
+            }
+            "--sign-key" => {
+                let path = args
+                    .next()
+                    .ok_or_else(|| err(format!("Missing key filename after {}", flag).into()))?;
+
+                if raw.sign_key.is_some() {
+                    return Err(err("Multiple signing keys specified".into()));
+                }
+                else {
+                    raw.sign_key = Some(path);
+                }
+
+                Ok(())
+            }
+            "--encrypt-key" => {
+                let path = args
+                    .next()
+                    .ok_or_else(|| err(format!("Missing key filename after {}", flag).into()))?;
+
+                if raw.encrypt_key.is_some() {
+                    return Err(err("Multiple encryption keys specified".into()));
+                }
+                else {
+                    raw.encrypt_key = Some(path);
+                }
+
+                Ok(())
+            }
+            "--key-env" | "--key-file" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| err(format!("Missing value after {}", flag).into()))?;
+
+                let value = ManifestString::new(value)
+                    .map_err(|_| err(format!("Value after {} is too long", flag).into()))?;
+
+                if raw.key_source.is_some() {
+                    return Err(err("Multiple key sources specified".into()));
+                }
+                else if flag == "--key-env" {
+                    raw.key_source = Some(KeySource::Env(value));
+                }
+                else {
+                    raw.key_source = Some(KeySource::File(value));
+                }
+
+                Ok(())
+            }
+            "--embed" => {
+                let spec = args
+                    .next()
+                    .ok_or_else(|| err(format!("Missing name=path after {}", flag).into()))?;
+                let (name, path) = spec
+                    .split_once('=')
+                    .filter(|(name, path)| name.is_empty() == false && path.is_empty() == false)
+                    .ok_or_else(|| {
+                        err(format!("Expected name=path after {}, got {}", flag, spec).into())
+                    })?;
+
+                let var_name = pixie::embed_var_name(name);
+                if raw.embeds.iter().any(|e| pixie::embed_var_name(e.name) == var_name) {
+                    let message = format!("Multiple embedded files would use {}", var_name);
+                    return Err(err(message.into()));
+                }
+                else {
+                    raw.embeds.push(EmbedArgs { name, path });
+                }
+
+                Ok(())
+            }
+            "--strip" => {
+                raw.strip = true;
+                Ok(())
+            }
+            "--relocate" => {
+                raw.relocate = true;
+                Ok(())
+            }
+            "--keep-debug" => {
+                let path = args
+                    .next()
+                    .ok_or_else(|| err(format!("Missing debug filename after {}", flag).into()))?;
+
+                if raw.keep_debug.is_some() {
+                    return Err(err("Multiple debug files specified".into()));
+                }
+                else {
+                    raw.keep_debug = Some(path);
+                }
+
+                Ok(())
+            }
+            "-u" | "--unpack" => {
+                raw.unpack = true;
+                Ok(())
+            }
             x => Err(err(format!("Unknown flag {}", x).into())),
         }
     }
@@ -12,6 +12,12 @@ pub enum Error {
     Deku(DekuError),
     /// pixie error: `{0}`
     Pixie(PixieError),
+    /// guests must either all be relocatable, or all be non-relocatable
+    MixedRelocatability,
+    /// guests must all be built for the same architecture
+    MixedMachines,
+    /// guests built for `{0:?}` aren't supported
+    UnsupportedMachine(pixie::ElfMachine),
 }
 
 impl From<EncoreError> for Error {
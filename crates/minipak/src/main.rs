@@ -6,6 +6,7 @@
 mod cli;
 mod error;
 
+#[cfg(target_arch = "x86_64")]
 #[naked]
 #[no_mangle]
 unsafe extern "C" fn _start() {
@@ -13,10 +14,18 @@ unsafe extern "C" fn _start() {
     asm!("mov rdi, rsp", "call pre_main", options(noreturn))
 }
 
+#[cfg(target_arch = "aarch64")]
+#[naked]
+#[no_mangle]
+unsafe extern "C" fn _start() {
+    use core::arch::asm;
+    asm!("mov x0, sp", "bl pre_main", options(noreturn))
+}
+
 use error::Error;
 use encore::prelude::*;
 use pixie::{
-    ObjectHeader, ProgramHeader, Resource, Writer
+    CompressedResource, PixieError, ProgramHeader, Resource, Writer
 };
 use core::ops::Range;
 
@@ -31,7 +40,11 @@ unsafe fn pre_main(stack_top: *mut u8) {
 fn main(env: Env) -> Result<(), Error> {
     match cli::Args::parse(&env) {
         Ok(args) => {
-            return write_compressed(&args);
+            return match args.mode {
+                cli::Mode::Pack => write_compressed(&args),
+                cli::Mode::Unpack => unpack(&args, &env),
+                cli::Mode::Info => info(&args, &env),
+            };
         },
         Err(err) => {
             println!("{}", err);
@@ -40,42 +53,313 @@ fn main(env: Env) -> Result<(), Error> {
     }
 }
 
+/// Returns a copy of a guest executable with only what's needed to run it.
+/// With `--keep-debug`, also writes what was dropped to a separate file.
+fn strip_guest(args: &cli::Args, executable: &[u8]) -> Result<Vec<u8>, Error> {
+    let obj = pixie::Object::new(executable)?;
+    let stripped = obj.stripped()?;
+    println!(
+        "Stripped guest from 0x{:x} to 0x{:x} bytes",
+        executable.len(),
+        stripped.len(),
+    );
+
+    if let Some(debug_path) = args.keep_debug {
+        let mut debug_output = Writer::new(debug_path, 0o644)?;
+        debug_output.write_all(&obj.debug_only()?)?;
+        println!("Written section headers, symbols and debug info to {}", debug_path);
+    }
+
+    Ok(stripped)
+}
+
+/// Returns whether stage2 can relocate a guest: it has to be relocatable,
+/// and not have an interpreter (which would relocate it instead)
+fn should_relocate(input: &str, executable: &[u8]) -> Result<bool, Error> {
+    let obj = pixie::Object::new(executable)?;
+    if obj.segments().load_convex_hull()?.start != 0 {
+        println!("Not relocating {}: it isn't relocatable", input);
+        return Ok(false);
+    }
+    if obj.segments().find(pixie::SegmentType::Interp).is_ok() {
+        println!("Not relocating {}: its interpreter will", input);
+        return Ok(false);
+    }
+
+    println!("Stage2 will relocate {}", input);
+    Ok(true)
+}
+
+/// Compresses a guest executable with the codec and filter mode from `args`,
+/// or, with `--best`, with every combination of compressing codec and
+/// explicit filter mode, keeping the smallest.
+/// Returns the codec used, the layout and the pieces.
+fn compress_guest(
+    args: &cli::Args,
+    executable: &[u8],
+) -> Result<(pixie::Codec, pixie::GuestLayout, Vec<u8>), Error> {
+    if args.best == false {
+        println!("Using {}, filter mode {}", args.codec.name(), args.filter.name());
+        let (layout, pieces, filter_stats) =
+            pixie::GuestLayout::compress(executable, args.codec, args.filter)?;
+        if filter_stats.unfiltered_len > 0 {
+            let (filtered, unfiltered) = (filter_stats.filtered_len, filter_stats.unfiltered_len);
+            println!(
+                "x86 filter: code segments compress to 0x{:x} bytes filtered, 0x{:x} unfiltered \
+                 in total ({}%), filtering {} of {}",
+                filtered,
+                unfiltered,
+                filtered * 100 / unfiltered,
+                filter_stats.filtered_segments,
+                filter_stats.segments,
+            );
+        }
+        return Ok((args.codec, layout, pieces));
+    }
+
+    let mut best: Option<(pixie::Codec, pixie::FilterMode, pixie::GuestLayout, Vec<u8>)> = None;
+    // storing pays off for mapping, not for size, and `auto` only picks
+    // between the explicit filter modes
+    for codec in pixie::Codec::COMPRESSING {
+        for filter in pixie::FilterMode::EXPLICIT {
+            let (layout, pieces, _) = pixie::GuestLayout::compress(executable, codec, filter)?;
+            println!(
+                "With {}, filter mode {}: 0x{:x} bytes",
+                codec.name(),
+                filter.name(),
+                pieces.len(),
+            );
+            // on ties, keep the earlier one: it's cheaper to decompress
+            if best.as_ref().map_or(true, |(_, _, _, best)| pieces.len() < best.len()) {
+                best = Some((codec, filter, layout, pieces));
+            }
+        }
+    }
+    let (codec, filter, layout, pieces) = best.expect("there's always at least one codec");
+    println!("Keeping {}, filter mode {}", codec.name(), filter.name());
+    Ok((codec, layout, pieces))
+}
+
+/// Compresses an embedded file with the codec from `args`, or, with
+/// `--best`, with whichever compressing codec does best. Returns the codec used and the
+/// compressed bytes.
+fn compress_embed(args: &cli::Args, data: &[u8]) -> (pixie::Codec, Vec<u8>) {
+    if args.best == false {
+        return (args.codec, args.codec.compress(data));
+    }
+
+    pixie::Codec::COMPRESSING
+        .iter()
+        .map(|codec| (*codec, codec.compress(data)))
+        .min_by_key(|(_, compressed)| compressed.len())
+        .expect("there's always at least one codec")
+}
+
+/// stage1 and stage2, as built for one guest architecture by `build.rs`
+struct Stages {
+    class: pixie::ElfClass,
+    machine: pixie::ElfMachine,
+    stage1: &'static [u8],
+    stage2: &'static [u8],
+}
+
+impl Stages {
+    /// Returns the stages that can run guests built for `machine`
+    fn for_machine(machine: pixie::ElfMachine) -> Result<Self, Error> {
+        // built for the same target as us
+        if machine == pixie::ElfMachine::NATIVE {
+            return Ok(Self {
+                class: pixie::ElfClass::NATIVE,
+                machine,
+                stage1: include_bytes!(concat!(env!("OUT_DIR"), "/embeds/libstage1.so")),
+                stage2: include_bytes!(concat!(env!("OUT_DIR"), "/embeds/libstage2.so")),
+            });
+        }
+
+        // see the `i386` feature
+        #[cfg(all(target_arch = "x86_64", feature = "i386"))]
+        if machine == pixie::ElfMachine::X86 {
+            return Ok(Self {
+                class: pixie::ElfClass::Elf32,
+                machine,
+                stage1: include_bytes!(concat!(
+                    env!("OUT_DIR"),
+                    "/embeds/i686-unknown-linux-gnu/libstage1.so"
+                )),
+                stage2: include_bytes!(concat!(
+                    env!("OUT_DIR"),
+                    "/embeds/i686-unknown-linux-gnu/libstage2.so"
+                )),
+            });
+        }
+
+        Err(Error::UnsupportedMachine(machine))
+    }
+}
+
 fn write_compressed(args: &cli::Args) -> Result<(), Error> {
-    println!("Packing guest {:?}", args.input);
-    let guest_file = File::open(args.input)?;
-    let guest_map = guest_file.map()?;
-    let guest_obj = pixie::Object::new(guest_map.as_ref())?;
+    let guest_files = args
+        .inputs
+        .iter()
+        .map(|input| {
+            println!("Packing guest {:?} as {}", input, pixie::guest_name(input));
+            File::open(input)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let guest_maps = guest_files
+        .iter()
+        .map(|file| file.map())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // stage1 reserves room for all guests: whichever one runs, the `brk`
+    // ends up past it.
+    let mut guest_hull: Option<Range<u64>> = None;
+    // The packed executable carries the guests' build IDs, so `file` and
+    // crash tooling can still find them.
+    let mut build_ids = Vec::new();
+    // The stages run the guest in-process, so they're built for its
+    // architecture, which all guests must share.
+    let mut machine = None;
+    for (input, guest_map) in args.inputs.iter().zip(&guest_maps) {
+        let obj = pixie::Object::new(guest_map.as_ref())?;
+        let guest_machine = obj.header().machine;
+        match machine {
+            None => machine = Some(guest_machine),
+            Some(machine) if machine != guest_machine => return Err(Error::MixedMachines),
+            Some(_) => {}
+        }
+        if let Some(note) = obj.build_id()? {
+            let hex = note.desc.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+            println!("Keeping build ID {} of {}", hex, input);
+            build_ids.push(note);
+        }
+
+        let hull = obj.segments().load_convex_hull()?;
+        guest_hull = Some(match guest_hull {
+            None => hull,
+            Some(acc) if (acc.start == 0) != (hull.start == 0) => {
+                return Err(Error::MixedRelocatability);
+            }
+            Some(acc) => core::cmp::min(acc.start, hull.start)..core::cmp::max(acc.end, hull.end),
+        });
+    }
+    let guest_hull = guest_hull.expect("there's always at least one guest");
+    let stages = Stages::for_machine(machine.expect("there's always at least one guest"))?;
+    println!("Using stages built for {:?}", stages.machine);
 
-    let guest_hull = guest_obj.segments().load_convex_hull()?;
-    let mut output = Writer::new(&args.output, 0o755)?;
-    relink_stage1(guest_hull, &mut output)?;
+    let output_path = args.output.expect("packing always has an output");
+    let mut output = Writer::new(output_path, 0o755)?;
+    relink_stage1(&stages, guest_hull, &build_ids, &mut output)?;
 
-    let stage2_slice = include_bytes!(concat!(env!("OUT_DIR"), "/embeds/libstage2.so"));
+    let stage2_slice = stages.stage2;
     let stage2_offset = output.offset();
     println!("Copying stage2 at 0x{:x}", stage2_offset);
     output.write_all(stage2_slice)?;
     output.align(0x8)?;
 
-    println!("Compressing guest...");
-    let compressed_guest = lz4_flex::compress_prepend_size(guest_map.as_ref());
-    let guest_offset = output.offset();
-    println!("Copying compressed guest at 0x{:x}", guest_offset);
-    output.write_all(&compressed_guest)?;
-    output.align(0x8)?;
+    let encrypt_key = match &args.encrypt {
+        Some(encrypt) => {
+            println!("Encrypting with key from {}", encrypt.key);
+            let key_file = File::open(encrypt.key)?;
+            let key = pixie::parse_key(key_file.map()?.as_ref())?;
+            println!("At run time, the key will be read from {}", encrypt.key_source);
+            Some((key, encrypt.key_source.clone()))
+        }
+        None => None,
+    };
+
+    let mut guests = Vec::new();
+    for (input, guest_map) in args.inputs.iter().zip(&guest_maps) {
+        let stripped;
+        let executable = if args.strip {
+            stripped = strip_guest(args, guest_map.as_ref())?;
+            &stripped[..]
+        } else {
+            guest_map.as_ref()
+        };
+
+        println!("Compressing guest {}...", input);
+        let (codec, layout, pieces) = compress_guest(args, executable)?;
+        if codec == pixie::Codec::Store {
+            // so its segments can be mapped straight from the packed
+            // executable, see `pixie::Guest::segment_file_offset`
+            output.align(0x1000)?;
+        }
+        let guest_offset = output.offset();
+        let (resource, encryption) = write_stored(
+            &mut output,
+            codec,
+            encrypt_key.as_ref(),
+            pieces,
+            executable,
+        )?;
+        println!(
+            "Copied compressed guest at 0x{:x}, in {} pieces",
+            guest_offset,
+            layout.segments.len() + 2,
+        );
+        guests.push(pixie::Guest {
+            name: pixie::ManifestString::new(pixie::guest_name(input))?,
+            encryption,
+            resource,
+            layout: Some(layout),
+            relocate: args.relocate && should_relocate(input, executable)?,
+            rest_codec: pixie::GuestLayout::rest_codec(codec),
+        });
+    }
+
+    let mut embeds = Vec::new();
+    for embed in &args.embeds {
+        println!("Embedding {} as {}", embed.path, embed.name);
+        let embed_file = File::open(embed.path)?;
+        let embed_map = embed_file.map()?;
+        let (codec, compressed) = compress_embed(args, embed_map.as_ref());
+        let (resource, encryption) = write_stored(
+            &mut output,
+            codec,
+            encrypt_key.as_ref(),
+            compressed,
+            embed_map.as_ref(),
+        )?;
+        let embed = pixie::NamedResource {
+            name: pixie::ManifestString::new(embed.name)?,
+            encryption,
+            resource,
+        };
+        println!(
+            "At run time, its path will be in ${}",
+            pixie::embed_var_name(embed.name.as_str()),
+        );
+        embeds.push(embed);
+    }
 
     let manifest_offset = output.offset();
     println!("Writing manifest at 0x{:x}", manifest_offset);
-    let manifest = pixie::Manifest {
+    let mut manifest = pixie::Manifest {
         stage2: Resource {
             offset: stage2_offset as _,
             len: stage2_slice.len(),
         },
-        guest: Resource {
-            offset: guest_offset as _,
-            len: compressed_guest.len(),
-        },
+        guests,
+        embeds,
+        signature: None,
     };
-    output.write_deku(&manifest)?;
+
+    if let Some(key_path) = args.sign_key {
+        println!("Signing with key from {}", key_path);
+        let key_file = File::open(key_path)?;
+        let seed = pixie::parse_key(key_file.map()?.as_ref())?;
+
+        // everything the manifest points to has been written by now
+        let written = output.file.map()?;
+        let public_key = manifest.sign(&seed, written.as_ref())?;
+        println!(
+            "Signed, public key: {}",
+            public_key.iter().map(|b| format!("{:02x}", b)).collect::<String>(),
+        );
+    }
+
+    output.write_all(&manifest.to_bytes()?)?;
     output.align(0x8)?;
 
     println!("Writing end marker");
@@ -84,18 +368,213 @@ fn write_compressed(args: &cli::Args) -> Result<(), Error> {
     };
     output.write_deku(&end_marker)?;
 
-    println!("Written to {}", args.output);
+    println!("Written to {}", output_path);
 
     Ok(())
 }
 
-fn relink_stage1(guest_hull: Range<u64>, writer: &mut Writer) -> Result<(), Error> {
-    let obj = pixie::Object::new(include_bytes!(
-        concat!(
-            env!("OUT_DIR"),
-            "/embeds/libstage1.so"
-        )
-    ))?;
+/// Encrypts `compressed` if there's a key, and writes the result to `output`.
+/// `original` is what `compressed` was compressed from, with `codec`. Returns
+/// how to find it again.
+fn write_stored(
+    output: &mut Writer,
+    codec: pixie::Codec,
+    encrypt_key: Option<&([u8; pixie::KEY_LEN], pixie::KeySource)>,
+    compressed: Vec<u8>,
+    original: &[u8],
+) -> Result<(CompressedResource, pixie::Encryption), Error> {
+    let (encryption, stored) = match encrypt_key {
+        Some((key, key_source)) => {
+            pixie::Encryption::encrypt(key, key_source.clone(), &compressed)?
+        }
+        None => (pixie::Encryption::None, compressed),
+    };
+
+    let offset = output.offset();
+    output.write_all(&stored)?;
+    output.align(0x8)?;
+
+    let original = matches!(encryption, pixie::Encryption::None).then(|| original);
+    let resource = CompressedResource::new(codec, offset as _, &stored, original);
+    Ok((resource, encryption))
+}
+
+fn unpack(args: &cli::Args, env: &Env) -> Result<(), Error> {
+    let input = args.inputs[0];
+    println!("Unpacking {:?}", input);
+    let packed_file = File::open(input)?;
+    let packed_map = packed_file.map()?;
+    let full_slice = packed_map.as_ref();
+    let manifest = pixie::Manifest::read_from_full_slice(full_slice)?;
+
+    // Unlike at run time, our own name doesn't pick a guest
+    let guest = match env.var(pixie::GUEST_VAR) {
+        Some(name) => manifest
+            .find_guest(name)
+            .ok_or_else(|| PixieError::GuestNotFound(name.into()))?,
+        None => &manifest.guests[0],
+    };
+    println!(
+        "Decompressing guest {} ({})...",
+        guest.name.as_str(),
+        guest.resource.codec.name(),
+    );
+    let guest = guest.load(full_slice, env)?;
+
+    let output_path = args.output.expect("unpacking always has an output");
+    let mut output = Writer::new(output_path, 0o755)?;
+    output.write_all(&guest)?;
+
+    println!("Written guest (0x{:x} bytes) to {}", guest.len(), output_path);
+
+    Ok(())
+}
+
+fn info(args: &cli::Args, env: &Env) -> Result<(), Error> {
+    let input = args.inputs[0];
+    let packed_file = File::open(input)?;
+    let packed_map = packed_file.map()?;
+    let full_slice = packed_map.as_ref();
+    let packed_obj = pixie::Object::new(full_slice)?;
+    let manifest = pixie::Manifest::read_from_full_slice(full_slice)?;
+
+    println!("{}:", input);
+    println!(
+        "  stage2: offset 0x{:x}, length 0x{:x}",
+        manifest.stage2.offset, manifest.stage2.len,
+    );
+    println!("  signed: {}", if manifest.is_signed() { "yes" } else { "no" });
+    for note in packed_obj.notes()?.iter().filter(|note| note.is_build_id()) {
+        let hex = note.desc.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        println!("  build ID: {}", hex);
+    }
+
+    // stage1's load convex hull starts at zero, so wherever the relinked
+    // segments start is the base offset we picked.
+    let base_offset = packed_obj.segments().load_convex_hull()?.start;
+    println!("  stage1 base offset: 0x{:x}", base_offset);
+
+    // `relink_stage1` always emits the pad segment as the last load segment.
+    if let Some(pad) = packed_obj.segments().of_type(pixie::SegmentType::Load).last() {
+        println!("  pad segment size: 0x{:x}", pad.header().mem_size);
+    }
+
+    for embed in &manifest.embeds {
+        println!(
+            "  embedded {}: offset 0x{:x}, length 0x{:x}, in ${}",
+            embed.name.as_str(),
+            embed.resource.resource.offset,
+            embed.resource.resource.len,
+            pixie::embed_var_name(embed.name.as_str()),
+        );
+    }
+
+    for (i, guest) in manifest.guests.iter().enumerate() {
+        print_guest_info(guest, i == 0, full_slice, env)?;
+    }
+
+    Ok(())
+}
+
+/// Prints where `guest` is stored and how, and what it looks like once
+/// unpacked (if we have the key to unpack it).
+fn print_guest_info(
+    guest: &pixie::Guest,
+    is_default: bool,
+    full_slice: &[u8],
+    env: &Env,
+) -> Result<(), Error> {
+    let resource = &guest.resource;
+    println!(
+        "  guest {}{}:",
+        guest.name.as_str(),
+        if is_default { " (default)" } else { "" },
+    );
+    println!(
+        "    offset 0x{:x}, length 0x{:x}, codec {}",
+        resource.resource.offset,
+        resource.resource.len,
+        resource.codec.name(),
+    );
+    match guest.encryption {
+        pixie::Encryption::None => println!(
+            "    checksums: 0x{:08x} compressed, 0x{:08x} uncompressed",
+            resource.compressed_crc32, resource.uncompressed_crc32,
+        ),
+        _ => println!("    checksum: 0x{:08x} compressed", resource.compressed_crc32),
+    }
+    match &guest.layout {
+        Some(layout) => {
+            println!(
+                "    pieces: headers 0x{:x}, {} load segments 0x{:x}, rest 0x{:x} \
+                 (uncompressed)",
+                layout.headers.uncompressed_len,
+                layout.segments.len(),
+                layout.segments.iter().map(|piece| piece.uncompressed_len).sum::<usize>(),
+                layout.rest.uncompressed_len,
+            );
+            let filters = layout
+                .segments
+                .iter()
+                .map(|piece| piece.filter.name())
+                .collect::<Vec<_>>();
+            println!("    load segment filters: {}", filters.join(", "));
+        }
+        None => println!("    legacy guest, stored as a whole: it can only be unpacked"),
+    }
+    if guest.relocate {
+        println!("    relocated by stage2");
+    }
+    match &guest.encryption {
+        pixie::Encryption::None => {
+            println!("    encryption: none");
+        }
+        pixie::Encryption::ChaCha20Poly1305 { key_source, .. } => {
+            println!("    encryption: chacha20-poly1305, key from {}", key_source);
+        }
+    }
+
+    let unpacked = match guest.load(full_slice, env) {
+        Ok(unpacked) => unpacked,
+        Err(e) if matches!(guest.encryption, pixie::Encryption::ChaCha20Poly1305 { .. }) => {
+            println!("    (cannot look inside the encrypted guest: {})", e);
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let stored_len = resource.resource.len;
+    println!(
+        "    size: 0x{:x} compressed, 0x{:x} uncompressed ({}%)",
+        stored_len,
+        unpacked.len(),
+        stored_len * 100 / core::cmp::max(unpacked.len(), 1),
+    );
+
+    let guest_obj = pixie::Object::new(&unpacked)?;
+    match guest_obj.segments().find(pixie::SegmentType::Interp) {
+        Ok(interp) => {
+            let interp = core::str::from_utf8(interp.slice())
+                .unwrap_or("(invalid UTF-8)")
+                .trim_end_matches('\0');
+            println!("    interpreter: {}", interp);
+        }
+        Err(_) => {
+            println!("    interpreter: (none)");
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes stage1, relinked so it's out of the way of the guest, along with
+/// `notes`
+fn relink_stage1(
+    stages: &Stages,
+    guest_hull: Range<u64>,
+    notes: &[pixie::Note],
+    writer: &mut Writer,
+) -> Result<(), Error> {
+    let obj = pixie::Object::new(stages.stage1)?;
 
     let hull = obj.segments().load_convex_hull()?;
     assert_eq!(hull.start, 0, "stage1 must be relocatable");
@@ -128,44 +607,27 @@ fn relink_stage1(guest_hull: Range<u64>, writer: &mut Writer) -> Result<(), Erro
     let entry_sym = mapped.lookup_sym("entry")?;
     let entry_point = base_offset + entry_sym.value;
 
-    let mut load_segs = obj
-        .segments()
-        .of_type(pixie::SegmentType::Load)
-        .collect::<Vec<_>>();
-
-    let out_header = ObjectHeader {
-        class: pixie::ElfClass::Elf64,
-        endianness: pixie::Endianness::Little,
-        version: 1,
-        os_abi: pixie::OsAbi::SysV,
-        r#type: pixie::ElfType::Exec,
-        machine: pixie::ElfMachine::X86_64,
-        version_bis: 1,
-        entry_point,
-
-        flags: 0,
-        hdr_size: ObjectHeader::SIZE,
-        // Two additional segments: one for `brk` alignment, and GNU_STACK.
-        ph_count: load_segs.len() as u16 + 2,
-        ph_offset: ObjectHeader::SIZE as _,
-        ph_entsize: ProgramHeader::SIZE,
-        // We're not adding any sections, our object will be opaque to debuggers
-        sh_count: 0,
-        sh_entsize: 0,
-        sh_nidx: 0,
-        sh_offset: 0,
-    };
-
-    writer.write_deku(&out_header)?;
+    let mut builder = pixie::ElfBuilder::new(stages.class, pixie::ElfType::Exec, stages.machine);
+    builder.entry_point(entry_point);
 
-    let static_headers = load_segs.iter().map(|seg| {
+    // Copy stage1's load segments. Our headers replace those in the first
+    // one, and what follows them (dynamic symbols, relocations...) isn't
+    // needed anymore, since we've just relocated stage1.
+    println!("Copying stage1 segments...");
+    for seg in obj.segments().of_type(pixie::SegmentType::Load) {
         let mut ph = seg.header().clone();
         ph.vaddr += base_offset;
         ph.paddr += base_offset;
-        ph
-    });
-    for ph in static_headers {
-        writer.write_deku(&ph)?;
+
+        let data = if seg.header().offset == 0 {
+            pixie::SegmentData::Headers
+        } else {
+            let start = seg.header().vaddr;
+            let end = start + seg.header().file_size;
+            pixie::SegmentData::Bytes(mapped.vaddr_slice(start..end)?)
+        };
+        println!("copying {:?}", ph);
+        builder.segment(ph, data);
     }
 
     // Insert dummy segment to offset the `brk` to its original position
@@ -192,7 +654,7 @@ fn relink_stage1(guest_hull: Range<u64>, writer: &mut Writer) -> Result<(), Erro
             r#type: pixie::SegmentType::Load,
             flags: ProgramHeader::WRITE | ProgramHeader::READ,
         };
-        writer.write_deku(&ph)?;
+        builder.segment(ph, pixie::SegmentData::None);
     }
 
     // Add a GNU_STACK program header for alignment and make it
@@ -208,34 +670,19 @@ fn relink_stage1(guest_hull: Range<u64>, writer: &mut Writer) -> Result<(), Erro
             r#type: pixie::SegmentType::GnuStack,
             flags: ProgramHeader::WRITE | ProgramHeader::READ,
         };
-        writer.write_deku(&ph)?;
+        builder.segment(ph, pixie::SegmentData::None);
     }
 
-    // Sort load segments by file offset and copy them.
-    {
-        load_segs.sort_by_key(|&seg| seg.header().offset);
-
-        println!("Copying stage1 segments...");
-        let copy_start_offset = writer.offset();
-        println!("copy_start_offset = 0x{:x}", copy_start_offset);
-        let copied_segments = load_segs
-            .into_iter()
-            .filter(move |seg| seg.header().offset > copy_start_offset);
-
-        for cp_seg in copied_segments {
-            let ph = cp_seg.header();
-            println!("copying {:?}", ph);
-
-            // Pad space between segments with zeros:
-            writer.pad(ph.offset - writer.offset())?;
-
-            // Then copy.
-            let start = ph.vaddr;
-            let len = ph.file_size;
-            let end = start + len;
-
-            writer.write_all(mapped.vaddr_slice(start..end))?;
+    for note in notes {
+        builder.note(*note);
+    }
+    // We're not adding any sections, our object will be opaque to debuggers
+    match builder.write(writer) {
+        Err(PixieError::NotesDontFit) => {
+            println!("WARNING: Build IDs don't fit in stage1's first segment, leaving them out.");
+            builder.clear_notes().write(writer)?;
         }
+        res => res?,
     }
 
     // Pad end of last segment with zeros:
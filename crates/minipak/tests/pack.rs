@@ -0,0 +1,337 @@
+use std::{
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+    process::{
+        Command,
+        Output,
+    },
+};
+
+/// The target minipak and these tests are built for, see `build.rs`
+const TARGET: &str = env!("MINIPAK_TARGET");
+
+/// What every guest prints, so tests can tell it ran
+const GREETING: &str = "hello from the guest";
+
+/// Prints `GREETING`, then the contents of the file embedded as `greeting`,
+/// if any
+const GUEST_SOURCE: &str = r#"
+fn main() {
+    println!("hello from the guest");
+    if let Ok(path) = std::env::var("MINIPAK_EMBED_GREETING") {
+        print!("{}", std::fs::read_to_string(path).unwrap());
+    }
+}
+"#;
+
+/// Has nothing but stage2 to relocate it: it checks a pointer that needs a
+/// `RELATIVE` relocation, and exits with 1 instead of printing `GREETING`
+/// if it's wrong
+#[cfg(target_arch = "x86_64")]
+const BARE_GUEST_SOURCE: &str = r#"
+#![no_std]
+#![no_main]
+
+use core::arch::{
+    asm,
+    global_asm,
+};
+
+static VALUE: u8 = 42;
+static POINTER: &u8 = &VALUE;
+
+global_asm!(".globl _start", "_start:", "call start");
+
+unsafe fn syscall(n: usize, a: usize, b: usize, c: usize) {
+    asm!("syscall", inlateout("rax") n => _, in("rdi") a, in("rsi") b, in("rdx") c,
+        lateout("rcx") _, lateout("r11") _);
+}
+
+#[no_mangle]
+unsafe extern "C" fn start() -> ! {
+    let pointer: &u8 = core::ptr::read_volatile(&POINTER);
+    let relocated = core::ptr::eq(pointer, &VALUE);
+    if relocated {
+        let message = "hello from the guest\n";
+        syscall(1, 1, message.as_ptr() as _, message.len());
+    }
+    syscall(60, relocated as usize ^ 1, 0, 0);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(_: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
+"#;
+
+/// Target the stages are built for with the `i386` feature, see `build.rs`
+#[cfg(all(target_arch = "x86_64", feature = "i386"))]
+const I686_TARGET: &str = "i686-unknown-linux-gnu";
+
+#[cfg(target_arch = "x86_64")]
+const AARCH64_TARGET: &str = "aarch64-unknown-linux-gnu";
+
+/// Returns an empty directory for the test named `name`
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Returns whether rustc has the standard library for `target`. The pinned
+/// nightly doesn't have it for every target in `rust-toolchain`, and it
+/// may not be installed.
+#[cfg(target_arch = "x86_64")]
+fn has_target(target: &str) -> bool {
+    let output = Command::new("rustc")
+        .args(["--print", "target-libdir", "--target", target])
+        .output()
+        .unwrap();
+    let libdir = String::from_utf8_lossy(&output.stdout);
+    output.status.success() && Path::new(libdir.trim()).exists()
+}
+
+/// Returns whether `program` is in the `PATH`
+#[cfg(target_arch = "x86_64")]
+fn has_program(program: &str) -> bool {
+    Command::new(program).arg("--version").output().is_ok()
+}
+
+/// Returns the qemu-user emulator to run `target` executables with, if the
+/// host can't run them
+fn emulator(target: &str) -> Option<&'static str> {
+    let host = env!("MINIPAK_HOST");
+    (target.starts_with("aarch64") && host.starts_with("aarch64") == false)
+        .then(|| "qemu-aarch64")
+}
+
+/// Returns a command running `program`, a `target` executable
+fn command(target: &str, program: &Path) -> Command {
+    match emulator(target) {
+        Some(emulator) => {
+            let mut command = Command::new(emulator);
+            command.arg(program);
+            command
+        }
+        None => Command::new(program),
+    }
+}
+
+/// Builds `GUEST_SOURCE` for `target` as a static executable. Where the
+/// target supports it, it's static-pie: relocatable, but without an
+/// interpreter, so stage2 is the one relocating it with `--relocate`.
+fn build_static_guest(dir: &Path, target: &str) -> PathBuf {
+    let source = dir.join("guest.rs");
+    fs::write(&source, GUEST_SOURCE).unwrap();
+
+    let guest = dir.join("guest");
+    let mut rustc = Command::new("rustc");
+    rustc
+        .arg("--target")
+        .arg(target)
+        .args(["-C", "target-feature=+crt-static"])
+        .args(["-C", "relocation-model=pie"])
+        .args(["-C", "panic=abort"])
+        .arg("-o")
+        .arg(&guest)
+        .arg(&source);
+    if emulator(target).is_some() {
+        // same as `.cargo/config.toml`
+        rustc.args(["-C", "linker=aarch64-linux-gnu-gcc"]);
+    }
+    assert_success("Building the guest", &rustc.output().unwrap());
+    guest
+}
+
+/// Builds `BARE_GUEST_SOURCE` as a static-pie executable, without start
+/// files or libc
+#[cfg(target_arch = "x86_64")]
+fn build_bare_guest(dir: &Path) -> PathBuf {
+    let source = dir.join("guest.rs");
+    fs::write(&source, BARE_GUEST_SOURCE).unwrap();
+
+    let guest = dir.join("guest");
+    let output = Command::new("rustc")
+        .arg("--target")
+        .arg(TARGET)
+        .args(["-C", "target-feature=+crt-static"])
+        .args(["-C", "relocation-model=pie"])
+        .args(["-C", "panic=abort"])
+        .args(["-C", "link-arg=-nostartfiles"])
+        .args(["-C", "link-arg=-nostdlib"])
+        .arg("-o")
+        .arg(&guest)
+        .arg(&source)
+        .output()
+        .unwrap();
+    assert_success("Building the guest", &output);
+    guest
+}
+
+/// Packs `guest` with `args`, returns the packed executable
+fn pack(dir: &Path, guest: &Path, args: &[&str]) -> PathBuf {
+    let minipak = Path::new(env!("CARGO_BIN_EXE_minipak"));
+    pack_with(minipak, TARGET, dir, guest, args)
+}
+
+/// Like `pack`, with a `minipak` built for `target`
+fn pack_with(minipak: &Path, target: &str, dir: &Path, guest: &Path, args: &[&str]) -> PathBuf {
+    let packed = dir.join("guest.pak");
+    let output = command(target, minipak)
+        .arg(guest)
+        .arg("-o")
+        .arg(&packed)
+        .args(args)
+        .output()
+        .unwrap();
+    assert_success("Packing", &output);
+    packed
+}
+
+/// Runs `packed`, checks it succeeded and returns what it printed
+fn run(packed: &Path) -> String {
+    run_for(TARGET, packed)
+}
+
+/// Like `run`, for a `target` executable
+fn run_for(target: &str, packed: &Path) -> String {
+    let output = command(target, packed).output().unwrap();
+    assert_success("Running the packed guest", &output);
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(stdout.contains(GREETING), "stdout: {}", stdout);
+    stdout
+}
+
+fn assert_success(what: &str, output: &Output) {
+    assert!(
+        output.status.success(),
+        "{} failed ({}).\nStdout: {}\nStderr: {}",
+        what,
+        output.status,
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr),
+    );
+}
+
+/// Prints why a test is skipped, for `--nocapture`
+#[cfg(target_arch = "x86_64")]
+fn skip(why: &str) {
+    eprintln!("skipping: {}", why);
+}
+
+/// Packs a static-pie guest for `target` with `--relocate`, runs it, and
+/// checks stage2 relocated it
+#[cfg(target_arch = "x86_64")]
+fn check_relocated_static_pie(name: &str, target: &str) {
+    if has_target(target) == false {
+        return skip(&format!("{} isn't installed", target));
+    }
+    let dir = scratch_dir(name);
+    let guest = build_static_guest(&dir, target);
+    let packed = pack(&dir, &guest, &["--relocate"]);
+
+    let stdout = run(&packed);
+    assert!(stdout.contains("[stage2] Relocating guest..."), "stdout: {}", stdout);
+}
+
+/// Also the smoke test for other architectures: stage1 and stage2's
+/// syscalls, entry points and `pixie::launch`
+#[test]
+fn runs_static_guest() {
+    let dir = scratch_dir("static");
+    let guest = build_static_guest(&dir, TARGET);
+    let packed = pack(&dir, &guest, &[]);
+    run(&packed);
+}
+
+/// Setting environment variables goes through `pixie::launch_with_env`,
+/// whose trampoline copies the new top of the stack
+#[test]
+fn hands_embeds_to_guest() {
+    let dir = scratch_dir("embed");
+    let guest = build_static_guest(&dir, TARGET);
+    let embed = dir.join("greeting.txt");
+    fs::write(&embed, "hello from the embed\n").unwrap();
+    let embed_arg = format!("greeting={}", embed.display());
+    let packed = pack(&dir, &guest, &["--embed", &embed_arg]);
+
+    let stdout = run(&packed);
+    assert!(stdout.contains("hello from the embed"), "stdout: {}", stdout);
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn relocates_static_pie_glibc_guest() {
+    check_relocated_static_pie("static-pie-glibc", "x86_64-unknown-linux-gnu");
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn relocates_static_pie_musl_guest() {
+    check_relocated_static_pie("static-pie-musl", "x86_64-unknown-linux-musl");
+}
+
+/// Unlike static-pie guests linked against a libc, this one can't relocate
+/// itself: it only runs if stage2 relocated it right
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn relocates_guest_without_start_files() {
+    let dir = scratch_dir("bare");
+    let guest = build_bare_guest(&dir);
+    let packed = pack(&dir, &guest, &["--relocate"]);
+    run(&packed);
+
+    // make sure the guest notices when it isn't relocated
+    let packed = pack(&dir, &guest, &[]);
+    let output = command(TARGET, &packed).output().unwrap();
+    assert_eq!(output.status.code(), Some(1), "unrelocated guest ran: {:?}", output);
+}
+
+/// Runs on stage1 and stage2 for i686, which minipak picks for 32-bit guests
+#[cfg(all(target_arch = "x86_64", feature = "i386"))]
+#[test]
+fn runs_i386_guest() {
+    if has_target(I686_TARGET) == false {
+        return skip(&format!("{} isn't installed", I686_TARGET));
+    }
+    let dir = scratch_dir("i386");
+    let guest = build_static_guest(&dir, I686_TARGET);
+    let packed = pack(&dir, &guest, &[]);
+    run(&packed);
+}
+
+/// Builds minipak for AArch64, and packs and runs a guest with it under
+/// qemu, like `cargo test --target aarch64-unknown-linux-gnu` would
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn runs_aarch64_guest_under_qemu() {
+    if has_target(AARCH64_TARGET) == false {
+        return skip(&format!("{} isn't installed", AARCH64_TARGET));
+    }
+    for program in ["qemu-aarch64", "aarch64-linux-gnu-gcc"] {
+        if has_program(program) == false {
+            return skip(&format!("{} isn't installed", program));
+        }
+    }
+
+    let dir = scratch_dir("aarch64");
+    let target_dir = dir.join("target");
+    let output = Command::new(env!("CARGO"))
+        .args(["build", "--release", "-p", "minipak", "--target", AARCH64_TARGET])
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+    assert_success("Building minipak for AArch64", &output);
+    let minipak = target_dir.join(AARCH64_TARGET).join("release/minipak");
+
+    let guest = build_static_guest(&dir, AARCH64_TARGET);
+    let packed = pack_with(&minipak, AARCH64_TARGET, &dir, &guest, &[]);
+    run_for(AARCH64_TARGET, &packed);
+}
\ No newline at end of file
@@ -0,0 +1,421 @@
+use crate::{
+    ElfClass,
+    ElfRecord,
+    Note,
+    ObjectHeader,
+    PixieError,
+    ProgramHeader,
+    SectionHeader,
+    SectionType,
+    SegmentType,
+    Writer,
+};
+use encore::prelude::*;
+
+/// What a segment added to an `ElfBuilder` holds in the file
+pub enum SegmentData<'a> {
+    /// Nothing: the segment only exists in memory, or not at all (like
+    /// `GNU_STACK`)
+    None,
+    /// The ELF header, the program headers and the notes. The segment is
+    /// placed at the start of the file, and grown to fit them if needed.
+    Headers,
+    /// These bytes, wherever they fit
+    Bytes(&'a [u8]),
+}
+
+struct BuilderSegment<'a> {
+    header: ProgramHeader,
+    data: SegmentData<'a>,
+}
+
+struct BuilderSection<'a> {
+    name: &'a str,
+    header: SectionHeader,
+    data: &'a [u8],
+}
+
+/// Lays out and writes an ELF object, from its segments, notes and
+/// (optionally) sections.
+///
+/// Program headers are written in the order segments were added, followed
+/// by a `PT_NOTE` one if there are any notes. File offsets and sizes are
+/// worked out by `write`, anything set in the headers passed is overwritten.
+pub struct ElfBuilder<'a> {
+    class: ElfClass,
+    typ: crate::ElfType,
+    machine: crate::ElfMachine,
+    entry_point: u64,
+    segments: Vec<BuilderSegment<'a>>,
+    notes: Vec<Note<'a>>,
+    sections: Vec<BuilderSection<'a>>,
+}
+
+/// Where `ElfBuilder::layout` put everything
+struct Layout {
+    /// Program headers, with their final offsets and sizes
+    program_headers: Vec<ProgramHeader>,
+    /// How many bytes the ELF header, program headers and notes span
+    headers_len: u64,
+    /// Offset of each section's contents
+    section_offsets: Vec<u64>,
+    /// Offset of `.shstrtab`, and of the section headers
+    shstrtab_offset: u64,
+    sh_offset: u64,
+}
+
+impl<'a> ElfBuilder<'a> {
+    /// Page size load segments are laid out for
+    const PAGE_SIZE: u64 = 0x1000;
+
+    pub fn new(class: ElfClass, typ: crate::ElfType, machine: crate::ElfMachine) -> Self {
+        Self {
+            class,
+            typ,
+            machine,
+            entry_point: 0,
+            segments: Default::default(),
+            notes: Default::default(),
+            sections: Default::default(),
+        }
+    }
+
+    /// Set the entry point. It must be in an executable load segment.
+    pub fn entry_point(&mut self, entry_point: u64) -> &mut Self {
+        self.entry_point = entry_point;
+        self
+    }
+
+    /// Add a segment. Load segments must be added in ascending address
+    /// order, and must not overlap.
+    pub fn segment(&mut self, header: ProgramHeader, data: SegmentData<'a>) -> &mut Self {
+        self.segments.push(BuilderSegment { header, data });
+        self
+    }
+
+    /// Add a note, to the `PT_NOTE` segment that follows the program headers
+    pub fn note(&mut self, note: Note<'a>) -> &mut Self {
+        self.notes.push(note);
+        self
+    }
+
+    /// Remove all notes added so far, for when they don't fit (see
+    /// `PixieError::NotesDontFit`)
+    pub fn clear_notes(&mut self) -> &mut Self {
+        self.notes.clear();
+        self
+    }
+
+    /// Add a section. Its name, offset and size are filled in when writing,
+    /// and section headers are only written if at least one was added.
+    pub fn section(&mut self, name: &'a str, header: SectionHeader, data: &'a [u8]) -> &mut Self {
+        self.sections.push(BuilderSection { name, header, data });
+        self
+    }
+
+    /// Checks that the segments make sense together
+    fn validate(&self) -> Result<(), PixieError> {
+        let invalid = |what| Err(PixieError::InvalidElfLayout(what));
+
+        let headers_segments = self
+            .segments
+            .iter()
+            .filter(|seg| matches!(seg.data, SegmentData::Headers))
+            .count();
+        if headers_segments > 1 {
+            return invalid("more than one segment holds the headers");
+        }
+
+        let mut seen_bytes = false;
+        for seg in self.segments.iter() {
+            let ph = &seg.header;
+            if let SegmentData::Bytes(data) = seg.data {
+                if ph.r#type == SegmentType::Load && data.len() as u64 > ph.mem_size {
+                    return invalid("load segment has more bytes than it spans in memory");
+                }
+            }
+            match seg.data {
+                SegmentData::Bytes(_) => seen_bytes = true,
+                SegmentData::Headers if seen_bytes => {
+                    return invalid("headers segment must come before segments with contents");
+                }
+                SegmentData::Headers
+                    if ph.r#type != SegmentType::Load || ph.vaddr % Self::PAGE_SIZE != 0 =>
+                {
+                    return invalid("headers must be in a page-aligned load segment");
+                }
+                _ => {}
+            }
+        }
+
+        let entry_in_code = self.segments.iter().any(|seg| {
+            seg.header.r#type == SegmentType::Load
+                && seg.header.flags & ProgramHeader::EXECUTE != 0
+                && seg.header.mem_range().contains(&self.entry_point)
+        });
+        if entry_in_code == false {
+            return invalid("entry point is not in an executable load segment");
+        }
+
+        Ok(())
+    }
+
+    /// Checks that load segments don't overlap in memory, once the headers
+    /// segment (if any) has been grown to fit the headers
+    fn validate_layout(layout: &Layout) -> Result<(), PixieError> {
+        let mut prev_end = None;
+        for ph in layout.program_headers.iter() {
+            if ph.r#type != SegmentType::Load {
+                continue;
+            }
+            if let Some(prev_end) = prev_end {
+                if ph.vaddr < prev_end {
+                    return Err(PixieError::InvalidElfLayout(
+                        "load segments overlap, or are out of order",
+                    ));
+                }
+            }
+            prev_end = Some(ph.vaddr + ph.mem_size);
+        }
+        Ok(())
+    }
+
+    /// Returns the number of program headers written, with or without the
+    /// `PT_NOTE` one
+    fn ph_count(&self, has_notes: bool) -> usize {
+        self.segments.len() + has_notes as usize
+    }
+
+    /// Returns `.shstrtab`: a null byte, followed by every section name
+    fn shstrtab(&self) -> Vec<u8> {
+        let mut shstrtab = alloc::vec![0u8];
+        for name in self.sections.iter().map(|s| s.name).chain(core::iter::once(".shstrtab")) {
+            shstrtab.extend_from_slice(name.as_bytes());
+            shstrtab.push(0);
+        }
+        shstrtab
+    }
+
+    /// Works out file offsets and sizes. Without notes (`notes_len` is
+    /// zero), there's no `PT_NOTE` segment.
+    fn layout(&self, notes_len: u64, shstrtab_len: u64) -> Layout {
+        let ph_table_end = ObjectHeader::size(self.class) as u64
+            + (self.ph_count(notes_len > 0) * ProgramHeader::size(self.class)) as u64;
+        let notes_offset = ceil(ph_table_end, Note::ALIGN);
+        let headers_len = notes_offset + notes_len;
+
+        let mut offset = headers_len;
+        let mut program_headers = Vec::new();
+        for seg in self.segments.iter() {
+            let mut ph = seg.header.clone();
+            match seg.data {
+                SegmentData::None => {
+                    ph.offset = 0;
+                    ph.file_size = 0;
+                }
+                SegmentData::Headers => {
+                    ph.offset = 0;
+                    ph.file_size = core::cmp::max(ph.file_size, headers_len);
+                    ph.mem_size = core::cmp::max(ph.mem_size, ph.file_size);
+                    offset = core::cmp::max(offset, ph.file_size);
+                }
+                SegmentData::Bytes(data) => {
+                    // Load segments must be at the same offset within a page,
+                    // in the file and in memory, so they can be mapped.
+                    ph.offset = if ph.r#type == SegmentType::Load {
+                        let page_offset = ph.vaddr % Self::PAGE_SIZE;
+                        let candidate = offset - offset % Self::PAGE_SIZE + page_offset;
+                        if candidate < offset {
+                            candidate + Self::PAGE_SIZE
+                        } else {
+                            candidate
+                        }
+                    } else {
+                        ceil(offset, ph.align.max(1))
+                    };
+                    ph.file_size = data.len() as _;
+                    offset = ph.offset + ph.file_size;
+                }
+            }
+            program_headers.push(ph);
+        }
+
+        if notes_len > 0 {
+            // The notes follow the program headers, so they're loaded along
+            // with them, if a segment holds those
+            let headers_vaddr = self
+                .segments
+                .iter()
+                .find(|seg| matches!(seg.data, SegmentData::Headers))
+                .map(|seg| seg.header.vaddr);
+            let (vaddr, mem_size) = match headers_vaddr {
+                Some(headers_vaddr) => (headers_vaddr + notes_offset, notes_len),
+                None => (0, 0),
+            };
+            program_headers.push(ProgramHeader {
+                r#type: SegmentType::Note,
+                flags: ProgramHeader::READ,
+                offset: notes_offset,
+                vaddr,
+                paddr: vaddr,
+                file_size: notes_len,
+                mem_size,
+                align: Note::ALIGN,
+            });
+        }
+
+        let mut section_offsets = Vec::new();
+        for section in self.sections.iter() {
+            offset = ceil(offset, section.header.addralign.max(1));
+            section_offsets.push(offset);
+            offset += section.data.len() as u64;
+        }
+        let shstrtab_offset = offset;
+        let sh_offset = ceil(shstrtab_offset + shstrtab_len, self.class.word_size() as u64);
+
+        Layout {
+            program_headers,
+            headers_len,
+            section_offsets,
+            shstrtab_offset,
+            sh_offset,
+        }
+    }
+
+    /// Lays out the object and writes it, starting at the writer's current
+    /// offset, which must be zero.
+    pub fn write(&self, writer: &mut Writer) -> Result<(), PixieError> {
+        self.validate()?;
+        if writer.offset() != 0 {
+            return Err(PixieError::InvalidElfLayout("object must start the file"));
+        }
+
+        let mut notes = Vec::new();
+        for note in self.notes.iter() {
+            notes.extend(note.to_bytes()?);
+        }
+        let shstrtab = self.shstrtab();
+        let layout = self.layout(notes.len() as _, shstrtab.len() as _);
+        // The headers segment grows to fit the notes, which may not leave
+        // room for the next one. Let callers tell that apart, to write the
+        // object without them.
+        if notes.is_empty() == false
+            && Self::validate_layout(&layout).is_err()
+            && Self::validate_layout(&self.layout(0, shstrtab.len() as _)).is_ok()
+        {
+            return Err(PixieError::NotesDontFit);
+        }
+        Self::validate_layout(&layout)?;
+        let has_sections = self.sections.is_empty() == false;
+
+        let sh_size = SectionHeader::size(self.class) as u16;
+        let header = ObjectHeader {
+            class: self.class,
+            endianness: crate::Endianness::Little,
+            version: 1,
+            os_abi: crate::OsAbi::SysV,
+            r#type: self.typ,
+            machine: self.machine,
+            version_bis: 1,
+            entry_point: self.entry_point,
+
+            flags: 0,
+            hdr_size: ObjectHeader::size(self.class),
+            ph_count: layout.program_headers.len() as _,
+            ph_offset: ObjectHeader::size(self.class) as _,
+            ph_entsize: ProgramHeader::size(self.class) as _,
+            // Null section, then ours, then `.shstrtab`
+            sh_count: if has_sections { self.sections.len() as u16 + 2 } else { 0 },
+            sh_entsize: if has_sections { sh_size } else { 0 },
+            sh_nidx: if has_sections { self.sections.len() as u16 + 1 } else { 0 },
+            sh_offset: if has_sections { layout.sh_offset } else { 0 },
+        };
+        writer.write_deku(&header)?;
+        for ph in layout.program_headers.iter() {
+            writer.write_all(&ph.to_class_bytes(self.class)?)?;
+        }
+        writer.align(Note::ALIGN)?;
+        writer.write_all(&notes)?;
+
+        // Segment contents, in file order
+        let mut contents = layout
+            .program_headers
+            .iter()
+            .zip(self.segments.iter())
+            .filter_map(|(ph, seg)| match seg.data {
+                SegmentData::Bytes(data) => Some((ph.offset, data)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        contents.sort_by_key(|(offset, _)| *offset);
+        for (offset, data) in contents {
+            if offset < writer.offset() {
+                return Err(PixieError::InvalidElfLayout("segments overlap in the file"));
+            }
+            writer.pad(offset - writer.offset())?;
+            writer.write_all(data)?;
+        }
+        // The headers segment may extend past everything else
+        let file_end = layout
+            .program_headers
+            .iter()
+            .map(|ph| ph.file_range().end)
+            .fold(layout.headers_len, core::cmp::max);
+        writer.pad(file_end.saturating_sub(writer.offset()))?;
+
+        if has_sections == false {
+            return Ok(());
+        }
+
+        let mut section_headers = alloc::vec![SectionHeader {
+            name: 0,
+            r#type: SectionType::Null,
+            flags: 0,
+            addr: 0,
+            offset: 0,
+            size: 0,
+            link: 0,
+            info: 0,
+            addralign: 0,
+            entsize: 0,
+        }];
+        let mut name = 1;
+        for (section, &offset) in self.sections.iter().zip(layout.section_offsets.iter()) {
+            writer.pad(offset - writer.offset())?;
+            writer.write_all(section.data)?;
+            section_headers.push(SectionHeader {
+                name,
+                offset,
+                size: section.data.len() as _,
+                ..section.header.clone()
+            });
+            name += section.name.len() as u32 + 1;
+        }
+        writer.pad(layout.shstrtab_offset - writer.offset())?;
+        writer.write_all(&shstrtab)?;
+        section_headers.push(SectionHeader {
+            name,
+            r#type: SectionType::StrTab,
+            flags: 0,
+            addr: 0,
+            offset: layout.shstrtab_offset,
+            size: shstrtab.len() as _,
+            link: 0,
+            info: 0,
+            addralign: 1,
+            entsize: 0,
+        });
+
+        writer.pad(layout.sh_offset - writer.offset())?;
+        for sh in section_headers.iter() {
+            writer.write_all(&sh.to_class_bytes(self.class)?)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Align `i` up to a multiple of `n`, a power of two
+fn ceil(i: u64, n: u64) -> u64 {
+    (i + n - 1) & !(n - 1)
+}
\ No newline at end of file
@@ -0,0 +1,97 @@
+use crate::PixieError;
+use alloc::vec::Vec;
+use deku::prelude::*;
+
+/// Compression codec a resource was stored with
+#[derive(Debug, Clone, Copy, PartialEq, DekuRead, DekuWrite)]
+#[deku(type = "u8")]
+pub enum Codec {
+    /// LZ4 block format, with the uncompressed size prepended. Fast to
+    /// decompress, modest ratio.
+    #[deku(id = "0")]
+    Lz4,
+    /// Raw DEFLATE (LZ77 + Huffman coding) at the highest level. Slower,
+    /// but noticeably smaller.
+    #[deku(id = "1")]
+    Deflate,
+    /// No compression at all. Guests stored this way are mapped straight
+    /// from the packed executable instead of being copied.
+    #[deku(id = "2")]
+    Store,
+}
+
+impl Codec {
+    /// Every codec we know how to compress and decompress
+    pub const ALL: [Codec; 3] = [Codec::Lz4, Codec::Deflate, Codec::Store];
+
+    /// The codecs that actually compress, cheapest to decompress first
+    pub const COMPRESSING: [Codec; 2] = [Codec::Lz4, Codec::Deflate];
+
+    /// Returns the name used to pick this codec on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Lz4 => "lz4",
+            Codec::Deflate => "deflate",
+            Codec::Store => "store",
+        }
+    }
+
+    /// Finds a codec by its command-line name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|codec| codec.name() == name)
+    }
+
+    /// Compresses `input`
+    pub fn compress(&self, input: &[u8]) -> Vec<u8> {
+        match self {
+            Codec::Lz4 => lz4_flex::compress_prepend_size(input),
+            Codec::Deflate => miniz_oxide::deflate::compress_to_vec(input, 10),
+            Codec::Store => input.to_vec(),
+        }
+    }
+
+    /// Decompresses `input`, which must have been produced by `compress`
+    pub fn decompress(&self, input: &[u8]) -> Result<Vec<u8>, PixieError> {
+        match self {
+            Codec::Lz4 => lz4_flex::decompress_size_prepended(input).ok(),
+            Codec::Deflate => miniz_oxide::inflate::decompress_to_vec(input).ok(),
+            Codec::Store => Some(input.to_vec()),
+        }
+        .ok_or(PixieError::DecompressionFailed(*self))
+    }
+
+    /// Compresses `input`, without recording its size: whoever decompresses
+    /// it with `decompress_into` needs to know it.
+    pub fn compress_block(&self, input: &[u8]) -> Vec<u8> {
+        match self {
+            Codec::Lz4 => lz4_flex::block::compress(input),
+            Codec::Deflate => miniz_oxide::deflate::compress_to_vec(input, 10),
+            Codec::Store => input.to_vec(),
+        }
+    }
+
+    /// Decompresses `input`, which must have been produced by
+    /// `compress_block`, into `output`, which must be exactly as large as
+    /// the original.
+    pub fn decompress_into(&self, input: &[u8], output: &mut [u8]) -> Result<(), PixieError> {
+        let written = match self {
+            Codec::Lz4 => lz4_flex::block::decompress_into(input, output).ok(),
+            Codec::Deflate => miniz_oxide::inflate::decompress_slice_iter_to_slice(
+                output,
+                core::iter::once(input),
+                false,
+                true,
+            )
+            .ok(),
+            Codec::Store if input.len() == output.len() => {
+                output.copy_from_slice(input);
+                Some(input.len())
+            }
+            Codec::Store => None,
+        };
+        match written {
+            Some(written) if written == output.len() => Ok(()),
+            _ => Err(PixieError::DecompressionFailed(*self)),
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,254 @@
+use crate::{
+    Manifest,
+    ManifestString,
+    PixieError,
+};
+use alloc::{
+    format,
+    vec::Vec,
+};
+use chacha20poly1305::{
+    aead::{
+        Aead,
+        NewAead,
+    },
+    ChaCha20Poly1305,
+    Key,
+    Nonce,
+};
+use deku::prelude::*;
+use ed25519_compact::{
+    KeyPair,
+    Noise,
+    PublicKey,
+    Seed,
+    Signature,
+};
+use encore::prelude::*;
+
+/// Size of the keys we accept, in bytes
+pub const KEY_LEN: usize = 32;
+
+/// Size of an Ed25519 signature, in bytes
+pub const SIGNATURE_LEN: usize = 64;
+
+/// Size of a ChaCha20-Poly1305 nonce, in bytes
+pub const NONCE_LEN: usize = 12;
+
+/// How a payload is encrypted at rest
+#[derive(Debug, Clone, DekuRead, DekuWrite)]
+#[deku(type = "u8")]
+pub enum Encryption {
+    #[deku(id = "0")]
+    None,
+    /// ChaCha20-Poly1305, with a key looked up when the executable runs
+    #[deku(id = "1")]
+    ChaCha20Poly1305 {
+        nonce: [u8; NONCE_LEN],
+        key_source: KeySource,
+    },
+}
+
+/// Where to find a decryption key when the packed executable runs
+#[derive(Debug, Clone, DekuRead, DekuWrite)]
+#[deku(type = "u8")]
+pub enum KeySource {
+    /// An environment variable holding the key as hexadecimal digits
+    #[deku(id = "0")]
+    Env(ManifestString),
+    /// A file holding the key as hexadecimal digits
+    #[deku(id = "1")]
+    File(ManifestString),
+}
+
+impl KeySource {
+    /// Looks up the key, using `env` for environment variables
+    pub fn load(&self, env: &Env) -> Result<[u8; KEY_LEN], PixieError> {
+        match self {
+            KeySource::Env(name) => {
+                let value = env
+                    .var(name.as_str())
+                    .ok_or_else(|| PixieError::KeyVarMissing(name.as_str().into()))?;
+                parse_key(value.as_bytes())
+            }
+            KeySource::File(path) => {
+                let file = File::open(path.as_str())?;
+                let map = file.map()?;
+                parse_key(map.as_ref())
+            }
+        }
+    }
+}
+
+impl core::fmt::Display for KeySource {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            KeySource::Env(name) => write!(f, "environment variable {}", name.as_str()),
+            KeySource::File(path) => write!(f, "file {}", path.as_str()),
+        }
+    }
+}
+
+impl Encryption {
+    /// Encrypts `plaintext` with `key` and a fresh random nonce. `key_source`
+    /// tells the packed executable where to find `key` again.
+    pub fn encrypt(
+        key: &[u8; KEY_LEN],
+        key_source: KeySource,
+        plaintext: &[u8],
+    ) -> Result<(Self, Vec<u8>), PixieError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        encore::random::fill(&mut nonce)?;
+
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|_| PixieError::EncryptionFailed)?;
+
+        Ok((Self::ChaCha20Poly1305 { nonce, key_source }, ciphertext))
+    }
+
+    /// Decrypts `ciphertext`, looking up the key with `env`. Fails if the
+    /// key is wrong or the ciphertext was tampered with.
+    pub fn decrypt(&self, ciphertext: &[u8], env: &Env) -> Result<Vec<u8>, PixieError> {
+        match self {
+            Encryption::None => Ok(ciphertext.into()),
+            Encryption::ChaCha20Poly1305 { nonce, key_source } => {
+                let key = key_source.load(env)?;
+                let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+                cipher
+                    .decrypt(Nonce::from_slice(nonce), ciphertext)
+                    .map_err(|_| PixieError::DecryptionFailed)
+            }
+        }
+    }
+}
+
+/// Parses a key written as hexadecimal digits, ignoring surrounding
+/// whitespace (so that key files can end with a newline).
+pub fn parse_key(text: &[u8]) -> Result<[u8; KEY_LEN], PixieError> {
+    let start = text.iter().position(|b| b.is_ascii_whitespace() == false).unwrap_or(0);
+    let end = text.iter().rposition(|b| b.is_ascii_whitespace() == false).map_or(0, |i| i + 1);
+    let text = text.get(start..end).unwrap_or_default();
+    if text.len() != KEY_LEN * 2 || text.iter().all(|b| b.is_ascii_hexdigit()) == false {
+        return Err(PixieError::InvalidKey);
+    }
+
+    let mut key = [0u8; KEY_LEN];
+    for (byte, digits) in key.iter_mut().zip(text.chunks(2)) {
+        *byte = hex_digit(digits[0]) << 4 | hex_digit(digits[1]);
+    }
+    Ok(key)
+}
+
+/// Returns the value of a hexadecimal digit, which must be one
+fn hex_digit(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        _ => digit - b'A' + 10,
+    }
+}
+
+impl Manifest {
+    /// Returns whether the manifest carries a signature at all
+    pub fn is_signed(&self) -> bool {
+        self.signature.is_some()
+    }
+
+    /// Returns the bytes of every resource, in the order they're signed
+    pub fn resource_slices<'a>(
+        &self,
+        full_slice: &'a [u8],
+    ) -> Result<Vec<&'a [u8]>, PixieError> {
+        let mut slices = alloc::vec![self.stage2.slice("stage2", full_slice)?];
+        for guest in &self.guests {
+            slices.push(guest.resource.resource.slice("guest", full_slice)?);
+        }
+        for embed in &self.embeds {
+            slices.push(embed.resource.resource.slice("embedded file", full_slice)?);
+        }
+        Ok(slices)
+    }
+
+    /// Signs the manifest along with the bytes of every resource it points to
+    /// in `full_slice`, the packed executable as written so far, using the
+    /// Ed25519 key derived from `seed`. Returns the matching public key.
+    pub fn sign(
+        &mut self,
+        seed: &[u8; KEY_LEN],
+        full_slice: &[u8],
+    ) -> Result<[u8; KEY_LEN], PixieError> {
+        let key_pair = KeyPair::from_seed(Seed::new(*seed));
+
+        let mut noise = [0u8; Noise::BYTES];
+        encore::random::fill(&mut noise)?;
+
+        let mut state = key_pair.sk.sign_incremental(Noise::new(noise));
+        state.absorb(self.signed_bytes()?);
+        for resource in self.resource_slices(full_slice)? {
+            state.absorb(resource);
+        }
+        self.signature = Some(*state.sign());
+
+        Ok(*key_pair.pk)
+    }
+
+    /// Checks the manifest's signature, over itself and every resource it
+    /// points to, against `public_key`.
+    ///
+    /// The manifest bytes are taken as they are in `full_slice`, so records
+    /// written by a newer minipak are covered too.
+    pub fn verify_signature(
+        &self,
+        full_slice: &[u8],
+        public_key: &[u8; KEY_LEN],
+    ) -> Result<(), PixieError> {
+        let signature = self.signature.ok_or(PixieError::MissingSignature)?;
+
+        let manifest_slice = Manifest::locate(full_slice)?;
+        let (_, signed_len) = Manifest::parse(manifest_slice)?;
+
+        let public_key = PublicKey::new(*public_key);
+        let mut state = public_key
+            .verify_incremental(&Signature::new(signature))
+            .map_err(|_| PixieError::BadSignature)?;
+        state.absorb(&manifest_slice[..signed_len]);
+        for resource in self.resource_slices(full_slice)? {
+            state.absorb(resource);
+        }
+        state.verify().map_err(|_| PixieError::BadSignature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_HEX: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191A1B1C1D1E1F";
+
+    #[test]
+    fn parses_key() {
+        let mut expected = [0u8; KEY_LEN];
+        for (i, byte) in expected.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        assert_eq!(parse_key(KEY_HEX.as_bytes()).unwrap(), expected);
+        let padded = alloc::format!("  {}\n", KEY_HEX);
+        assert_eq!(parse_key(padded.as_bytes()).unwrap(), expected);
+    }
+
+    #[test]
+    fn rejects_invalid_keys() {
+        // 64 bytes, but "é" is two of them, straddling a pair of digits
+        let multibyte = alloc::format!("0{}{}", "é", &KEY_HEX[3..]);
+        let plus = alloc::format!("+1{}", &KEY_HEX[2..]);
+        let short = &KEY_HEX[2..];
+        let texts: [&[u8]; 5] =
+            [multibyte.as_bytes(), plus.as_bytes(), short.as_bytes(), b"", b" \n"];
+        for text in texts {
+            let res = parse_key(text);
+            assert!(matches!(res, Err(PixieError::InvalidKey)), "{:?}", res);
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,172 @@
+use alloc::vec::Vec;
+use deku::prelude::*;
+
+/// Reversible transform applied to data before compressing it, to make it
+/// compress better
+#[derive(Debug, Clone, Copy, PartialEq, DekuRead, DekuWrite)]
+#[deku(type = "u8")]
+pub enum Filter {
+    #[deku(id = "0")]
+    None,
+    /// x86 branch/call/jump (BCJ) filter: turns the relative targets of
+    /// `call` and `jmp` (opcodes E8 and E9) into absolute ones, so that
+    /// calls to the same function look the same. `ip` is the address the
+    /// data is loaded at.
+    #[deku(id = "1")]
+    X86 { ip: u64 },
+}
+
+impl Filter {
+    /// Returns a short name for this filter, for humans
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filter::None => "none",
+            Filter::X86 { .. } => "x86",
+        }
+    }
+
+    /// Applies the filter to `data`, in place
+    pub fn encode(&self, data: &mut [u8]) {
+        match self {
+            Filter::None => {}
+            Filter::X86 { ip } => x86(data, *ip, true),
+        }
+    }
+
+    /// Undoes `encode`, in place
+    pub fn decode(&self, data: &mut [u8]) {
+        match self {
+            Filter::None => {}
+            Filter::X86 { ip } => x86(data, *ip, false),
+        }
+    }
+
+    /// Returns a filtered copy of `data`
+    pub fn encoded(&self, data: &[u8]) -> Vec<u8> {
+        let mut data = data.to_vec();
+        self.encode(&mut data);
+        data
+    }
+}
+
+/// Which filters to apply when compressing a guest
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterMode {
+    /// Never filter
+    None,
+    /// Filter x86-64 code segments, whether it helps or not
+    X86,
+    /// Filter x86-64 code segments when it makes them compress smaller
+    Auto,
+}
+
+impl FilterMode {
+    /// Every filter mode, cheapest to decode first
+    pub const ALL: [FilterMode; 3] = [FilterMode::None, FilterMode::X86, FilterMode::Auto];
+
+    /// The modes that don't pick for themselves, cheapest to decode first.
+    /// `Auto` only ever picks one of these for each segment.
+    pub const EXPLICIT: [FilterMode; 2] = [FilterMode::None, FilterMode::X86];
+
+    /// Returns the name used to pick this mode on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterMode::None => "none",
+            FilterMode::X86 => "x86",
+            FilterMode::Auto => "auto",
+        }
+    }
+
+    /// Finds a filter mode by its command-line name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|mode| mode.name() == name)
+    }
+}
+
+/// Converts the targets of E8/E9 instructions in `data`, loaded at `ip`,
+/// from relative to absolute (when `encode` is set) or back.
+///
+/// Every E8/E9 byte is treated as an instruction and its operand skipped,
+/// so which bytes get converted only depends on bytes that are never
+/// converted: decoding picks the exact same operands encoding did.
+fn x86(data: &mut [u8], ip: u64, encode: bool) {
+    let mut i = 0;
+    while i + 5 <= data.len() {
+        if data[i] != 0xE8 && data[i] != 0xE9 {
+            i += 1;
+            continue;
+        }
+
+        let operand = &mut data[i + 1..i + 5];
+        let value = u32::from_le_bytes([operand[0], operand[1], operand[2], operand[3]]);
+        // relative targets are relative to the next instruction
+        let next = (ip as u32).wrapping_add(i as u32 + 5);
+        let converted = if encode {
+            value.wrapping_add(next)
+        } else {
+            value.wrapping_sub(next)
+        };
+        operand.copy_from_slice(&converted.to_le_bytes());
+
+        i += 5;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// x86-64 code with calls and jumps both ways, an E8 byte inside an
+    /// immediate, and an E8 byte too close to the end to have an operand
+    const CODE: [u8; 38] = [
+        0x55, // push rbp
+        0x48, 0x89, 0xe5, // mov rbp, rsp
+        0xe8, 0x10, 0x00, 0x00, 0x00, // call +0x10
+        0x89, 0xc7, // mov edi, eax
+        0xe8, 0xf0, 0xff, 0xff, 0xff, // call -0x10
+        0x48, 0x85, 0xc0, // test rax, rax
+        0x74, 0x05, // je +5
+        0xe9, 0x20, 0x01, 0x00, 0x00, // jmp +0x120
+        0x5d, // pop rbp
+        0xc3, // ret
+        0xb8, 0xe8, 0x00, 0x00, 0x00, // mov eax, 0xe8
+        0x90, 0x90, 0x90, 0x90, // nop
+        0xe8,
+    ];
+
+    fn round_trip(filter: Filter, data: &[u8]) {
+        let mut decoded = filter.encoded(data);
+        filter.decode(&mut decoded);
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn x86_round_trips_code() {
+        let filter = Filter::X86 { ip: 0x401000 };
+        round_trip(filter, &CODE);
+
+        // the first call's target is now absolute: after the call, plus 0x10
+        let encoded = filter.encoded(&CODE);
+        assert_eq!(encoded[5..9], (0x401000u32 + 9 + 0x10).to_le_bytes());
+    }
+
+    #[test]
+    fn x86_round_trips_random_bytes() {
+        // xorshift, anything that gives plenty of E8 and E9 bytes will do
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut data = vec![0u8; 64 * 1024];
+        for byte in &mut data {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            *byte = state as u8;
+        }
+
+        for ip in [0, 0x401000, 0xffff_ffff_ffff_f000] {
+            for len in [0, 1, 4, 5, 6, data.len()] {
+                round_trip(Filter::X86 { ip }, &data[..len]);
+            }
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,146 @@
+use super::{
+    prelude::*,
+    ElfClass,
+};
+use deku::bitvec::{
+    BitSlice,
+    BitVec,
+    BitView,
+    Msb0,
+};
+
+/// A record laid out differently in ELF32 and ELF64 objects. The type itself
+/// has the ELF64 layout, and is read from (and written as) an ELF32 twin
+/// when given `ElfClass::Elf32` as deku context.
+pub trait ElfRecord: Sized {
+    /// Returns the size of the record, in objects of class `class`
+    fn size(class: ElfClass) -> usize;
+
+    /// Parses a record from the start of `input`, returning what's left
+    fn from_class_bytes(input: &[u8], class: ElfClass) -> Result<(&[u8], Self), DekuError>;
+
+    /// Serializes the record, as laid out in objects of class `class`
+    fn to_class_bytes(&self, class: ElfClass) -> Result<Vec<u8>, DekuError>;
+}
+
+/// Implements `ElfRecord` (and deku reading and writing with an `ElfClass`
+/// context) for `$typ`, whose ELF32 layout is `$typ32`. `$typ` must convert
+/// from `$typ32`, and `$typ32` must (fallibly) convert from `&$typ`.
+macro_rules! elf_record {
+    ($typ:ty, $typ32:ty, $size64:expr, $size32:expr) => {
+        impl<'a> $crate::deku::DekuRead<'a, $crate::ElfClass> for $typ {
+            fn read(
+                input: &'a $crate::deku::bitvec::BitSlice<u8, $crate::deku::bitvec::Msb0>,
+                class: $crate::ElfClass,
+            ) -> Result<
+                (&'a $crate::deku::bitvec::BitSlice<u8, $crate::deku::bitvec::Msb0>, Self),
+                $crate::deku::DekuError,
+            > {
+                use $crate::deku::DekuRead;
+                match class {
+                    $crate::ElfClass::Elf32 => {
+                        let (rest, record) = <$typ32 as DekuRead<'a, ()>>::read(input, ())?;
+                        Ok((rest, record.into()))
+                    }
+                    _ => <$typ as DekuRead<'a, ()>>::read(input, ()),
+                }
+            }
+        }
+
+        impl $crate::deku::DekuWrite<$crate::ElfClass> for $typ {
+            fn write(
+                &self,
+                output: &mut $crate::deku::bitvec::BitVec<u8, $crate::deku::bitvec::Msb0>,
+                class: $crate::ElfClass,
+            ) -> Result<(), $crate::deku::DekuError> {
+                use $crate::deku::DekuWrite;
+                match class {
+                    $crate::ElfClass::Elf32 => {
+                        let record = <$typ32>::try_from(self)?;
+                        DekuWrite::<()>::write(&record, output, ())
+                    }
+                    _ => DekuWrite::<()>::write(self, output, ()),
+                }
+            }
+        }
+
+        impl $crate::ElfRecord for $typ {
+            fn size(class: $crate::ElfClass) -> usize {
+                match class {
+                    $crate::ElfClass::Elf32 => $size32,
+                    _ => $size64,
+                }
+            }
+
+            fn from_class_bytes(
+                input: &[u8],
+                class: $crate::ElfClass,
+            ) -> Result<(&[u8], Self), $crate::deku::DekuError> {
+                $crate::format::read_record(input, class)
+            }
+
+            fn to_class_bytes(
+                &self,
+                class: $crate::ElfClass,
+            ) -> Result<alloc::vec::Vec<u8>, $crate::deku::DekuError> {
+                $crate::format::write_record(self, class)
+            }
+        }
+    };
+}
+
+/// Shared by `ElfRecord` implementations, see `elf_record!`
+pub(crate) fn read_record<'a, T>(
+    input: &'a [u8],
+    class: ElfClass,
+) -> Result<(&'a [u8], T), DekuError>
+where
+    T: DekuRead<'a, ElfClass>,
+{
+    let (rest, record) = T::read(input.view_bits(), class)?;
+    // records are always a whole number of bytes
+    Ok((&input[input.len() - rest.len() / 8..], record))
+}
+
+/// Shared by `ElfRecord` implementations, see `elf_record!`
+pub(crate) fn write_record<T>(record: &T, class: ElfClass) -> Result<Vec<u8>, DekuError>
+where
+    T: DekuWrite<ElfClass>,
+{
+    let mut output = BitVec::new();
+    record.write(&mut output, class)?;
+    Ok(output.into_vec())
+}
+
+/// Narrows an address-sized value for an ELF32 record, failing if it
+/// doesn't fit
+pub(crate) fn word32(value: u64) -> Result<u32, DekuError> {
+    u32::try_from(value)
+        .map_err(|_| DekuError::InvalidParam(format!("0x{:x} doesn't fit in ELF32", value)))
+}
+
+/// Reads an address-sized value: 4 bytes in ELF32 objects, 8 in ELF64 ones
+pub(crate) fn read_word(
+    input: &BitSlice<u8, Msb0>,
+    class: ElfClass,
+) -> Result<(&BitSlice<u8, Msb0>, u64), DekuError> {
+    match class {
+        ElfClass::Elf32 => {
+            let (rest, value) = u32::read(input, ())?;
+            Ok((rest, value as u64))
+        }
+        _ => u64::read(input, ()),
+    }
+}
+
+/// Writes an address-sized value, see `read_word`
+pub(crate) fn write_word(
+    output: &mut BitVec<u8, Msb0>,
+    class: ElfClass,
+    value: u64,
+) -> Result<(), DekuError> {
+    match class {
+        ElfClass::Elf32 => word32(value)?.write(output, ()),
+        _ => value.write(output, ()),
+    }
+}
\ No newline at end of file
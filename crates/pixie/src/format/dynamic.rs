@@ -1,11 +1,53 @@
-use super::prelude::*;
+use super::{
+    prelude::*,
+    read_word,
+    word32,
+    write_word,
+    ElfClass,
+};
+use deku::bitvec::{
+    BitSlice,
+    BitVec,
+    Msb0,
+};
 
+/// An entry of the `DYNAMIC` segment. This is the ELF64 layout, see
+/// `ElfRecord` to read and write ELF32 ones.
 #[derive(Debug, Clone, DekuRead,DekuWrite)]
 pub struct DynamicTag {
     pub typ: DynamicTagType,
     pub addr: u64,
 }
 
+/// The ELF32 layout of a dynamic entry: same fields, narrower words
+#[derive(DekuRead, DekuWrite)]
+struct DynamicTag32 {
+    typ: u32,
+    addr: u32,
+}
+
+impl From<DynamicTag32> for DynamicTag {
+    fn from(tag: DynamicTag32) -> Self {
+        Self {
+            typ: DynamicTagType::from_id(tag.typ as _),
+            addr: tag.addr as _,
+        }
+    }
+}
+
+impl TryFrom<&DynamicTag> for DynamicTag32 {
+    type Error = DekuError;
+
+    fn try_from(tag: &DynamicTag) -> Result<Self, DekuError> {
+        Ok(Self {
+            typ: word32(tag.typ.id())?,
+            addr: word32(tag.addr)?,
+        })
+    }
+}
+
+elf_record!(DynamicTag, DynamicTag32, 16, 8);
+
 #[derive(Debug, Clone, Copy, DekuRead,DekuWrite, PartialEq)]
 #[deku(type = "u64")]
 pub enum DynamicTagType {
@@ -13,6 +55,8 @@ pub enum DynamicTagType {
     Null,
     #[deku(id = "2")]
     PltRelSz,
+    #[deku(id = "4")]
+    Hash,
     #[deku(id = "5")]
     StrTab,
     #[deku(id = "6")]
@@ -21,10 +65,148 @@ pub enum DynamicTagType {
     Rela,
     #[deku(id = "8")]
     RelaSz,
+    #[deku(id = "10")]
+    StrSz,
     #[deku(id = "11")]
     SymEnt,
+    /// Like `Rela`, for relocations without an explicit addend
+    #[deku(id = "17")]
+    Rel,
+    #[deku(id = "18")]
+    RelSz,
+    /// Whether `JmpRel` relocations are `Rela` or `Rel` ones
+    #[deku(id = "20")]
+    PltRel,
     #[deku(id = "23")]
     JmpRel,
+    #[deku(id = "0x6ffffef5")]
+    GnuHash,
+    #[deku(id = "0x6ffffff0")]
+    VerSym,
+    #[deku(id = "0x6ffffffc")]
+    VerDef,
+    #[deku(id = "0x6ffffffd")]
+    VerDefNum,
+    #[deku(id = "0x6ffffffe")]
+    VerNeed,
+    #[deku(id = "0x6fffffff")]
+    VerNeedNum,
     #[deku(id_pat = "_")]
     Other(u64),
+}
+
+impl DynamicTagType {
+    /// Returns the tag type with the given number
+    pub fn from_id(id: u64) -> Self {
+        Self::from_bytes((&id.to_ne_bytes(), 0)).map_or(Self::Other(id), |(_, typ)| typ)
+    }
+
+    /// Returns this tag type's number
+    pub fn id(&self) -> u64 {
+        let bytes = self.to_bytes().unwrap_or_default();
+        <[u8; 8]>::try_from(bytes).map_or(0, u64::from_ne_bytes)
+    }
+}
+
+/// A `DT_HASH` table (System V hash table). Symbol `i` is found by hashing
+/// its name with `sysv_hash`, and following `chains` from that hash's bucket.
+#[derive(Debug, Clone, DekuRead, DekuWrite)]
+pub struct HashTable {
+    pub nbucket: u32,
+    /// Also the number of symbols in the symbol table
+    pub nchain: u32,
+    #[deku(count = "nbucket")]
+    pub buckets: Vec<u32>,
+    #[deku(count = "nchain")]
+    pub chains: Vec<u32>,
+}
+
+/// The start of a `DT_GNU_HASH` table. It's followed by one chain entry
+/// (the symbol's hash, whose lowest bit marks the end of a chain) per symbol
+/// from `symoffset` on, which is only known by walking them.
+#[derive(Debug, Clone, DekuRead, DekuWrite)]
+#[deku(ctx = "class: ElfClass")]
+pub struct GnuHashTable {
+    /// Class of the object the table is from: bloom filter words are as
+    /// wide as its addresses
+    #[deku(skip, default = "class")]
+    pub class: ElfClass,
+    pub nbuckets: u32,
+    /// Index of the first symbol covered by the table
+    pub symoffset: u32,
+    pub bloom_size: u32,
+    pub bloom_shift: u32,
+    #[deku(
+        reader = "read_words(deku::rest, *bloom_size as usize, class)",
+        writer = "write_words(deku::output, &self.bloom, class)"
+    )]
+    pub bloom: Vec<u64>,
+    /// Index of the first symbol of each chain, or zero for empty chains
+    #[deku(count = "nbuckets")]
+    pub buckets: Vec<u32>,
+}
+
+impl GnuHashTable {
+    /// Returns where chain entries start, from the start of the table
+    pub fn chains_offset(&self) -> usize {
+        16 + self.bloom.len() * self.class.word_size() + self.buckets.len() * 4
+    }
+
+    /// Returns false if the bloom filter rules out a symbol with hash `h`
+    pub fn may_contain(&self, h: u32) -> bool {
+        if self.bloom.is_empty() {
+            return true;
+        }
+        let bits = self.class.word_size() as u32 * 8;
+        let word = self.bloom[((h / bits) as usize) % self.bloom.len()];
+        let mask = (1u64 << (h % bits)) | (1u64 << ((h >> self.bloom_shift) % bits));
+        word & mask == mask
+    }
+}
+
+/// Reads `count` address-sized words, see `read_word`
+fn read_words(
+    mut input: &BitSlice<u8, Msb0>,
+    count: usize,
+    class: ElfClass,
+) -> Result<(&BitSlice<u8, Msb0>, Vec<u64>), DekuError> {
+    let mut words = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (rest, word) = read_word(input, class)?;
+        words.push(word);
+        input = rest;
+    }
+    Ok((input, words))
+}
+
+/// Writes address-sized words, see `write_word`
+fn write_words(
+    output: &mut BitVec<u8, Msb0>,
+    words: &[u64],
+    class: ElfClass,
+) -> Result<(), DekuError> {
+    for &word in words {
+        write_word(output, class, word)?;
+    }
+    Ok(())
+}
+
+/// The hash function of `DT_HASH` tables
+pub fn sysv_hash(name: &[u8]) -> u32 {
+    let mut h: u32 = 0;
+    for &c in name {
+        h = (h << 4).wrapping_add(c as u32);
+        let g = h & 0xf000_0000;
+        if g != 0 {
+            h ^= g >> 24;
+        }
+        h &= !g;
+    }
+    h
+}
+
+/// The hash function of `DT_GNU_HASH` tables
+pub fn gnu_hash(name: &[u8]) -> u32 {
+    name.iter()
+        .fold(5381u32, |h, &c| h.wrapping_mul(33).wrapping_add(c as u32))
 }
\ No newline at end of file
@@ -1,6 +1,11 @@
-use super::prelude::*;
+use super::{
+    prelude::*,
+    read_word,
+    write_word,
+};
 
-/// An ELF object header
+/// An ELF object header. Addresses and offsets are 4 bytes wide in ELF32
+/// objects, and 8 in ELF64 ones, depending on `class`.
 #[derive(Derivative, Clone, PartialEq, DekuRead, DekuWrite)]
 #[derivative(Debug)]
 #[deku(magic = b"\x7FELF")]
@@ -17,11 +22,23 @@ pub struct ObjectHeader {
     /// Always 1
     pub version_bis: u32,
     #[derivative(Debug(format_with = "hex_fmt"))]
+    #[deku(
+        reader = "read_word(deku::rest, *class)",
+        writer = "write_word(deku::output, self.class, self.entry_point)"
+    )]
     pub entry_point: u64,
     
     #[derivative(Debug(format_with = "hex_fmt"))]
+    #[deku(
+        reader = "read_word(deku::rest, *class)",
+        writer = "write_word(deku::output, self.class, self.ph_offset)"
+    )]
     pub ph_offset: u64,
     #[derivative(Debug(format_with = "hex_fmt"))]
+    #[deku(
+        reader = "read_word(deku::rest, *class)",
+        writer = "write_word(deku::output, self.class, self.sh_offset)"
+    )]
     pub sh_offset: u64,
 
     #[derivative(Debug(format_with = "hex_fmt"))]
@@ -37,7 +54,16 @@ pub struct ObjectHeader {
 }
 
 impl ObjectHeader {
+    /// Size of an ELF64 object header, see `size`
     pub const SIZE: u16 = 64;
+
+    /// Returns the size of the object header in objects of class `class`
+    pub fn size(class: ElfClass) -> u16 {
+        match class {
+            ElfClass::Elf32 => 52,
+            _ => Self::SIZE,
+        }
+    }
 }
 
 #[derive(Clone, Copy, DekuRead, DekuWrite, Debug, PartialEq)]
@@ -51,6 +77,23 @@ pub enum ElfClass {
     Other(u8),
 }
 
+impl ElfClass {
+    /// The class of objects built for the current target
+    #[cfg(target_pointer_width = "64")]
+    pub const NATIVE: Self = Self::Elf64;
+    /// The class of objects built for the current target
+    #[cfg(target_pointer_width = "32")]
+    pub const NATIVE: Self = Self::Elf32;
+
+    /// Returns the size of addresses and offsets in objects of this class
+    pub fn word_size(&self) -> usize {
+        match self {
+            Self::Elf32 => 4,
+            _ => 8,
+        }
+    }
+}
+
 #[derive(Clone, Copy, DekuRead, DekuWrite, Debug, PartialEq)]
 #[deku(type = "u8")]
 pub enum Endianness {
@@ -78,10 +121,24 @@ pub enum ElfMachine {
     X86,
     #[deku(id = "0x3e")]
     X86_64,
+    #[deku(id = "0xb7")]
+    AArch64,
     #[deku(id_pat = "_")]
     Other(u16),
 }
 
+impl ElfMachine {
+    /// The machine of objects built for the current target
+    #[cfg(target_arch = "x86_64")]
+    pub const NATIVE: Self = Self::X86_64;
+    /// The machine of objects built for the current target
+    #[cfg(target_arch = "x86")]
+    pub const NATIVE: Self = Self::X86;
+    /// The machine of objects built for the current target
+    #[cfg(target_arch = "aarch64")]
+    pub const NATIVE: Self = Self::AArch64;
+}
+
 #[derive(Clone, Copy, DekuRead, DekuWrite, Debug, PartialEq)]
 #[deku(type = "u16")]
 pub enum ElfType {
@@ -1,11 +1,18 @@
 mod prelude;
 
+#[macro_use]
+mod class;
+pub use class::*;
+
 mod header;
 pub use header::*;
 
 mod program_header;
 pub use program_header::*;
 
+mod section_header;
+pub use section_header::*;
+
 mod dynamic;
 pub use dynamic::*;
 
@@ -13,4 +20,10 @@ mod sym;
 pub use sym::*;
 
 mod rela;
-pub use rela::*;
\ No newline at end of file
+pub use rela::*;
+
+mod version;
+pub use version::*;
+
+mod note;
+pub use note::*;
\ No newline at end of file
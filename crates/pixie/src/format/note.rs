@@ -0,0 +1,88 @@
+use super::prelude::*;
+use crate::PixieError;
+
+/// The fixed-size part of an ELF note, found in `PT_NOTE` segments
+#[derive(Debug, DekuRead, DekuWrite, Clone)]
+pub struct NoteHeader {
+    /// Length of the name, including its null terminator
+    pub name_size: u32,
+    pub desc_size: u32,
+    pub typ: u32,
+}
+
+/// An ELF note: a name saying who defined it, a type that only means
+/// something for that name, and some contents.
+#[derive(Debug, Clone, Copy)]
+pub struct Note<'a> {
+    pub name: &'a str,
+    pub typ: u32,
+    pub desc: &'a [u8],
+}
+
+impl<'a> Note<'a> {
+    /// Name of notes defined by GNU tools
+    pub const GNU: &'static str = "GNU";
+    /// Type of the GNU note holding the build ID
+    pub const GNU_BUILD_ID: u32 = 3;
+    /// Notes (and their fields) are padded to this, unless their segment
+    /// asks for more. It's what notes we write are padded to.
+    pub const ALIGN: u64 = 4;
+
+    /// Parses every note in `slice`, the contents of a `PT_NOTE` segment
+    /// (or `SHT_NOTE` section) aligned to `seg_align`. Notes in 8-aligned
+    /// ones, like GNU property notes, pad their fields to 8 bytes, any other
+    /// alignment is taken as 4, like readelf does.
+    pub fn parse_all(mut slice: &'a [u8], seg_align: u64) -> Result<Vec<Self>, PixieError> {
+        let to = if seg_align == 8 { 8 } else { Self::ALIGN as usize };
+        let mut notes = Vec::new();
+        while slice.is_empty() == false {
+            let (_, header) = NoteHeader::from_bytes((slice, 0))?;
+            let name_start = 12;
+            let desc_start = name_start + align(header.name_size as usize, to);
+            let end = desc_start + align(header.desc_size as usize, to);
+
+            let name = slice
+                .get(name_start..name_start + header.name_size as usize)
+                .ok_or(PixieError::MalformedNotes)?;
+            let name = name.strip_suffix(&[0]).unwrap_or(name);
+            let name = core::str::from_utf8(name).map_err(|_| PixieError::MalformedNotes)?;
+            let desc = slice
+                .get(desc_start..desc_start + header.desc_size as usize)
+                .ok_or(PixieError::MalformedNotes)?;
+
+            notes.push(Self {
+                name,
+                typ: header.typ,
+                desc,
+            });
+            slice = slice.get(end..).unwrap_or_default();
+        }
+        Ok(notes)
+    }
+
+    /// Returns true if this is the GNU build ID note
+    pub fn is_build_id(&self) -> bool {
+        self.name == Self::GNU && self.typ == Self::GNU_BUILD_ID
+    }
+
+    /// Serializes the note, padded as it would be in a `PT_NOTE` segment
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DekuError> {
+        let header = NoteHeader {
+            name_size: self.name.len() as u32 + 1,
+            desc_size: self.desc.len() as u32,
+            typ: self.typ,
+        };
+        let mut output = header.to_bytes()?;
+        output.extend_from_slice(self.name.as_bytes());
+        output.push(0);
+        output.resize(align(output.len(), Self::ALIGN as usize), 0);
+        output.extend_from_slice(self.desc);
+        output.resize(align(output.len(), Self::ALIGN as usize), 0);
+        Ok(output)
+    }
+}
+
+/// Rounds `len` up to a multiple of `to`, a power of two
+fn align(len: usize, to: usize) -> usize {
+    (len + to - 1) & !(to - 1)
+}
\ No newline at end of file
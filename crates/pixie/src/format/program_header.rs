@@ -1,6 +1,11 @@
-use super::prelude::*;
+use super::{
+    prelude::*,
+    word32,
+};
+use encore::syscall::MmapProt;
 
-/// A program header (loader view, segment mapped into memory)
+/// A program header (loader view, segment mapped into memory). This is the
+/// ELF64 layout, see `ElfRecord` to read and write ELF32 ones.
 #[derive(Derivative, DekuRead, DekuWrite, Clone)]
 #[derivative(Debug)]
 pub struct ProgramHeader {
@@ -21,6 +26,53 @@ pub struct ProgramHeader {
     pub align: u64,
 }
 
+/// The ELF32 layout of a program header: `flags` moved, words are narrower
+#[derive(DekuRead, DekuWrite)]
+struct ProgramHeader32 {
+    r#type: SegmentType,
+    offset: u32,
+    vaddr: u32,
+    paddr: u32,
+    file_size: u32,
+    mem_size: u32,
+    flags: u32,
+    align: u32,
+}
+
+impl From<ProgramHeader32> for ProgramHeader {
+    fn from(ph: ProgramHeader32) -> Self {
+        Self {
+            r#type: ph.r#type,
+            flags: ph.flags,
+            offset: ph.offset as _,
+            vaddr: ph.vaddr as _,
+            paddr: ph.paddr as _,
+            file_size: ph.file_size as _,
+            mem_size: ph.mem_size as _,
+            align: ph.align as _,
+        }
+    }
+}
+
+impl TryFrom<&ProgramHeader> for ProgramHeader32 {
+    type Error = DekuError;
+
+    fn try_from(ph: &ProgramHeader) -> Result<Self, DekuError> {
+        Ok(Self {
+            r#type: ph.r#type,
+            offset: word32(ph.offset)?,
+            vaddr: word32(ph.vaddr)?,
+            paddr: word32(ph.paddr)?,
+            file_size: word32(ph.file_size)?,
+            mem_size: word32(ph.mem_size)?,
+            flags: ph.flags,
+            align: word32(ph.align)?,
+        })
+    }
+}
+
+elf_record!(ProgramHeader, ProgramHeader32, 56, 32);
+
 #[derive(Debug, DekuRead, DekuWrite, Clone, Copy, PartialEq)]
 #[deku(type = "u32")]
 pub enum SegmentType {
@@ -32,15 +84,21 @@ pub enum SegmentType {
     Dynamic,
     #[deku(id = "0x3")]
     Interp,
+    #[deku(id = "0x4")]
+    Note,
     #[deku(id = "0x7")]
     Tls,
     #[deku(id = "0x6474e551")]
     GnuStack,
+    /// Made read-only once relocations are applied
+    #[deku(id = "0x6474e552")]
+    GnuRelro,
     #[deku(id_pat = "_")]
     Other(u32),
 }
 
 impl ProgramHeader {
+    /// Size of an ELF64 program header, see `ElfRecord::size`
     pub const SIZE: u16 = 56;
 
     pub const EXECUTE: u32 = 1;
@@ -54,4 +112,19 @@ impl ProgramHeader {
     pub fn mem_range(&self) -> core::ops::Range<u64> {
         self.vaddr..(self.vaddr  + self.mem_size)
     }
+
+    /// Returns the memory protection matching this segment's flags
+    pub fn prot(&self) -> MmapProt {
+        let mut prot = MmapProt::empty();
+        if self.flags & Self::READ != 0 {
+            prot |= MmapProt::READ;
+        }
+        if self.flags & Self::WRITE != 0 {
+            prot |= MmapProt::WRITE;
+        }
+        if self.flags & Self::EXECUTE != 0 {
+            prot |= MmapProt::EXEC;
+        }
+        prot
+    }
 }
\ No newline at end of file
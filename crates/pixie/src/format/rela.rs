@@ -1,5 +1,10 @@
-use super::prelude::*;
+use super::{
+    prelude::*,
+    word32,
+};
 
+/// A relocation with an explicit addend. This is the ELF64 layout, see
+/// `ElfRecord` to read and write ELF32 ones.
 #[derive(Debug, DekuRead, DekuWrite, Clone)]
 pub struct Rela {
     pub offset: u64,
@@ -8,6 +13,102 @@ pub struct Rela {
     pub addend: u64,
 }
 
+/// A relocation whose addend is whatever is at `offset` already, as used by
+/// i386 objects. This is the ELF64 layout, see `ElfRecord` to read and write
+/// ELF32 ones.
+#[derive(Debug, DekuRead, DekuWrite, Clone)]
+pub struct Rel {
+    pub offset: u64,
+    pub typ: RelType,
+    pub sym: u32,
+}
+
+impl Rel {
+    /// Turns this into a `Rela`, given the value at `offset`
+    pub fn with_addend(&self, addend: u64) -> Rela {
+        Rela {
+            offset: self.offset,
+            typ: self.typ,
+            sym: self.sym,
+            addend,
+        }
+    }
+}
+
+/// The ELF32 layout of a relocation: the type only takes up the lowest byte
+/// of the info word, and i386 numbers them differently (see `RelType`)
+#[derive(DekuRead, DekuWrite)]
+struct Rela32 {
+    offset: u32,
+    typ: u8,
+    #[deku(bytes = "3")]
+    sym: u32,
+    addend: i32,
+}
+
+impl From<Rela32> for Rela {
+    fn from(rela: Rela32) -> Self {
+        Self {
+            offset: rela.offset as _,
+            typ: RelType::from_i386(rela.typ),
+            sym: rela.sym,
+            addend: rela.addend as i64 as u64,
+        }
+    }
+}
+
+impl TryFrom<&Rela> for Rela32 {
+    type Error = DekuError;
+
+    fn try_from(rela: &Rela) -> Result<Self, DekuError> {
+        Ok(Self {
+            offset: word32(rela.offset)?,
+            typ: rela.typ.to_i386()?,
+            sym: rela.sym,
+            addend: rela.addend as i64 as i32,
+        })
+    }
+}
+
+elf_record!(Rela, Rela32, 24, 12);
+
+/// The ELF32 layout of a relocation without an addend, see `Rela32`
+#[derive(DekuRead, DekuWrite)]
+struct Rel32 {
+    offset: u32,
+    typ: u8,
+    #[deku(bytes = "3")]
+    sym: u32,
+}
+
+impl From<Rel32> for Rel {
+    fn from(rel: Rel32) -> Self {
+        Self {
+            offset: rel.offset as _,
+            typ: RelType::from_i386(rel.typ),
+            sym: rel.sym,
+        }
+    }
+}
+
+impl TryFrom<&Rel> for Rel32 {
+    type Error = DekuError;
+
+    fn try_from(rel: &Rel) -> Result<Self, DekuError> {
+        Ok(Self {
+            offset: word32(rel.offset)?,
+            typ: rel.typ.to_i386()?,
+            sym: rel.sym,
+        })
+    }
+}
+
+elf_record!(Rel, Rel32, 16, 8);
+
+/// Relocation types, as numbered on x86_64. i386 ones that work the same way
+/// (on 4-byte words) are read as their x86_64 equivalent: `R_386_32` as
+/// `_64`, `R_386_TLS_TPOFF` as `TpOff64`, and so on. AArch64 ones are read as
+/// `Other`, see `from_aarch64`.
 #[derive(Debug, DekuRead, DekuWrite, Clone, Copy, PartialEq)]
 #[deku(type = "u32")]
 pub enum RelType {
@@ -19,10 +120,78 @@ pub enum RelType {
     GlobDat,
     #[deku(id = "7")]
     JumpSlot,
+    #[deku(id = "5")]
+    Copy,
     #[deku(id = "8")]
     Relative,
     #[deku(id = "16")]
     DtpMod64,
+    #[deku(id = "17")]
+    DtpOff64,
+    #[deku(id = "18")]
+    TpOff64,
+    #[deku(id = "37")]
+    IRelative,
     #[deku(id_pat = "_")]
     Other(u32),
+}
+
+impl RelType {
+    /// Maps an i386 relocation type to its x86_64 equivalent
+    fn from_i386(typ: u8) -> Self {
+        match typ {
+            0 => Self::Null,
+            1 => Self::_64,
+            5 => Self::Copy,
+            6 => Self::GlobDat,
+            7 => Self::JumpSlot,
+            8 => Self::Relative,
+            14 => Self::TpOff64,
+            35 => Self::DtpMod64,
+            36 => Self::DtpOff64,
+            42 => Self::IRelative,
+            // there's no x86_64 equivalent, keep it out of the way of those
+            // that do have one
+            typ => Self::Other(0x100 + typ as u32),
+        }
+    }
+
+    /// Maps an AArch64 relocation type to its x86_64 equivalent. AArch64
+    /// numbers them past all the x86_64 ones, so they've been read as
+    /// `Other`: `R_AARCH64_ABS64` as `Other(257)` and so on.
+    pub(crate) fn from_aarch64(self) -> Self {
+        match self {
+            Self::Other(257) => Self::_64,
+            Self::Other(1024) => Self::Copy,
+            Self::Other(1025) => Self::GlobDat,
+            Self::Other(1026) => Self::JumpSlot,
+            Self::Other(1027) => Self::Relative,
+            Self::Other(1028) => Self::DtpMod64,
+            Self::Other(1029) => Self::DtpOff64,
+            Self::Other(1030) => Self::TpOff64,
+            Self::Other(1032) => Self::IRelative,
+            typ => typ,
+        }
+    }
+
+    /// Maps a relocation type back to its i386 number, see `from_i386`
+    fn to_i386(self) -> Result<u8, DekuError> {
+        Ok(match self {
+            Self::Null => 0,
+            Self::_64 => 1,
+            Self::Copy => 5,
+            Self::GlobDat => 6,
+            Self::JumpSlot => 7,
+            Self::Relative => 8,
+            Self::TpOff64 => 14,
+            Self::DtpMod64 => 35,
+            Self::DtpOff64 => 36,
+            Self::IRelative => 42,
+            Self::Other(typ) if (0x100..0x200).contains(&typ) => (typ - 0x100) as u8,
+            Self::Other(typ) => {
+                let message = format!("relocation type {} has no i386 equivalent", typ);
+                return Err(DekuError::InvalidParam(message));
+            }
+        })
+    }
 }
\ No newline at end of file
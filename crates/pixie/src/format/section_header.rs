@@ -0,0 +1,145 @@
+use super::{
+    prelude::*,
+    word32,
+};
+
+/// A section header (linker view, not needed to run the object). This is
+/// the ELF64 layout, see `ElfRecord` to read and write ELF32 ones.
+#[derive(Derivative, DekuRead, DekuWrite, Clone)]
+#[derivative(Debug)]
+pub struct SectionHeader {
+    /// Offset of the section's name in `.shstrtab`
+    pub name: u32,
+    pub r#type: SectionType,
+    #[derivative(Debug(format_with = "hex_fmt"))]
+    pub flags: u64,
+    #[derivative(Debug(format_with = "hex_fmt"))]
+    pub addr: u64,
+    #[derivative(Debug(format_with = "hex_fmt"))]
+    pub offset: u64,
+    #[derivative(Debug(format_with = "hex_fmt"))]
+    pub size: u64,
+    pub link: u32,
+    pub info: u32,
+    #[derivative(Debug(format_with = "hex_fmt"))]
+    pub addralign: u64,
+    #[derivative(Debug(format_with = "hex_fmt"))]
+    pub entsize: u64,
+}
+
+/// The ELF32 layout of a section header: same fields, narrower words
+#[derive(DekuRead, DekuWrite)]
+struct SectionHeader32 {
+    name: u32,
+    r#type: SectionType,
+    flags: u32,
+    addr: u32,
+    offset: u32,
+    size: u32,
+    link: u32,
+    info: u32,
+    addralign: u32,
+    entsize: u32,
+}
+
+impl From<SectionHeader32> for SectionHeader {
+    fn from(sh: SectionHeader32) -> Self {
+        Self {
+            name: sh.name,
+            r#type: sh.r#type,
+            flags: sh.flags as _,
+            addr: sh.addr as _,
+            offset: sh.offset as _,
+            size: sh.size as _,
+            link: sh.link,
+            info: sh.info,
+            addralign: sh.addralign as _,
+            entsize: sh.entsize as _,
+        }
+    }
+}
+
+impl TryFrom<&SectionHeader> for SectionHeader32 {
+    type Error = DekuError;
+
+    fn try_from(sh: &SectionHeader) -> Result<Self, DekuError> {
+        Ok(Self {
+            name: sh.name,
+            r#type: sh.r#type,
+            flags: word32(sh.flags)?,
+            addr: word32(sh.addr)?,
+            offset: word32(sh.offset)?,
+            size: word32(sh.size)?,
+            link: sh.link,
+            info: sh.info,
+            addralign: word32(sh.addralign)?,
+            entsize: word32(sh.entsize)?,
+        })
+    }
+}
+
+elf_record!(SectionHeader, SectionHeader32, 64, 40);
+
+#[derive(Debug, DekuRead, DekuWrite, Clone, Copy, PartialEq)]
+#[deku(type = "u32")]
+pub enum SectionType {
+    #[deku(id = "0")]
+    Null,
+    #[deku(id = "1")]
+    ProgBits,
+    #[deku(id = "2")]
+    SymTab,
+    #[deku(id = "3")]
+    StrTab,
+    #[deku(id = "4")]
+    Rela,
+    #[deku(id = "5")]
+    Hash,
+    #[deku(id = "6")]
+    Dynamic,
+    #[deku(id = "7")]
+    Note,
+    /// Takes up space in memory but not in the file, like `.bss`
+    #[deku(id = "8")]
+    NoBits,
+    #[deku(id = "9")]
+    Rel,
+    #[deku(id = "11")]
+    DynSym,
+    #[deku(id = "14")]
+    InitArray,
+    #[deku(id = "15")]
+    FiniArray,
+    #[deku(id = "0x6ffffff6")]
+    GnuHash,
+    #[deku(id = "0x6ffffffd")]
+    GnuVerDef,
+    #[deku(id = "0x6ffffffe")]
+    GnuVerNeed,
+    #[deku(id = "0x6fffffff")]
+    GnuVerSym,
+    #[deku(id_pat = "_")]
+    Other(u32),
+}
+
+impl SectionHeader {
+    /// Size of an ELF64 section header, see `ElfRecord::size`
+    pub const SIZE: u16 = 64;
+
+    pub const WRITE: u64 = 0x1;
+    pub const ALLOC: u64 = 0x2;
+    pub const EXECINSTR: u64 = 0x4;
+
+    /// Section index meaning "look in the first section header instead",
+    /// used when there are too many sections to fit in the object header
+    pub const XINDEX: u16 = 0xffff;
+
+    /// Returns the range the section spans in the file, which is empty for
+    /// `NoBits` sections
+    pub fn file_range(&self) -> core::ops::Range<u64> {
+        match self.r#type {
+            SectionType::NoBits => self.offset..self.offset,
+            _ => self.offset..(self.offset + self.size),
+        }
+    }
+}
\ No newline at end of file
@@ -1,5 +1,10 @@
-use super::prelude::*;
+use super::{
+    prelude::*,
+    word32,
+};
 
+/// A symbol table entry. This is the ELF64 layout, see `ElfRecord` to read
+/// and write ELF32 ones.
 #[derive(Debug, DekuRead, DekuWrite, Clone)]
 pub struct Sym {
     pub name: u32,
@@ -13,6 +18,59 @@ pub struct Sym {
     pub size: u64,
 }
 
+/// The ELF32 layout of a symbol: the value and size come first, and are
+/// narrower
+#[derive(DekuRead, DekuWrite)]
+struct Sym32 {
+    name: u32,
+    value: u32,
+    size: u32,
+
+    bind: SymBind,
+    #[deku(pad_bytes_after = "1")]
+    typ: SymType,
+
+    shndx: u16,
+}
+
+impl From<Sym32> for Sym {
+    fn from(sym: Sym32) -> Self {
+        Self {
+            name: sym.name,
+            bind: sym.bind,
+            typ: sym.typ,
+            shndx: sym.shndx,
+            value: sym.value as _,
+            size: sym.size as _,
+        }
+    }
+}
+
+impl TryFrom<&Sym> for Sym32 {
+    type Error = DekuError;
+
+    fn try_from(sym: &Sym) -> Result<Self, DekuError> {
+        Ok(Self {
+            name: sym.name,
+            value: word32(sym.value)?,
+            size: word32(sym.size)?,
+            bind: sym.bind,
+            typ: sym.typ,
+            shndx: sym.shndx,
+        })
+    }
+}
+
+elf_record!(Sym, Sym32, 24, 16);
+
+impl Sym {
+    /// Returns true if the object defines this symbol, rather than needing
+    /// it from elsewhere
+    pub fn is_defined(&self) -> bool {
+        self.shndx != 0
+    }
+}
+
 #[derive(Debug, DekuRead, DekuWrite, Clone, Copy, PartialEq)]
 #[deku(type = "u8", bits = 4)]
 pub enum SymBind {
@@ -0,0 +1,75 @@
+use super::prelude::*;
+
+/// An entry of the `DT_VERSYM` table: the version of the symbol with the
+/// same index
+#[derive(Debug, Clone, Copy, PartialEq, DekuRead, DekuWrite)]
+pub struct Versym(pub u16);
+
+impl Versym {
+    /// Local symbol, not versioned
+    pub const LOCAL: u16 = 0;
+    /// Global symbol, not versioned
+    pub const GLOBAL: u16 = 1;
+    const HIDDEN: u16 = 0x8000;
+
+    /// Returns the index of the version, matching `Verdef::ndx` or
+    /// `Vernaux::other`
+    pub fn index(&self) -> u16 {
+        self.0 & !Self::HIDDEN
+    }
+
+    /// Returns true if the symbol isn't the default version of its name
+    pub fn is_hidden(&self) -> bool {
+        self.0 & Self::HIDDEN != 0
+    }
+}
+
+/// A version the object defines, in the `DT_VERDEF` table
+#[derive(Debug, Clone, DekuRead, DekuWrite)]
+pub struct Verdef {
+    pub version: u16,
+    pub flags: u16,
+    pub ndx: u16,
+    /// Number of `Verdaux` entries, the first one holds the name
+    pub cnt: u16,
+    pub hash: u32,
+    /// Offset of the first `Verdaux`, from the start of this entry
+    pub aux: u32,
+    /// Offset of the next `Verdef`, from the start of this entry, or zero
+    pub next: u32,
+}
+
+/// A name for a `Verdef`
+#[derive(Debug, Clone, DekuRead, DekuWrite)]
+pub struct Verdaux {
+    /// Offset in the string table
+    pub name: u32,
+    pub next: u32,
+}
+
+/// A dependency the object needs versions from, in the `DT_VERNEED` table
+#[derive(Debug, Clone, DekuRead, DekuWrite)]
+pub struct Verneed {
+    pub version: u16,
+    /// Number of `Vernaux` entries
+    pub cnt: u16,
+    /// Offset of the dependency's name in the string table
+    pub file: u32,
+    /// Offset of the first `Vernaux`, from the start of this entry
+    pub aux: u32,
+    /// Offset of the next `Verneed`, from the start of this entry, or zero
+    pub next: u32,
+}
+
+/// A version needed from a dependency
+#[derive(Debug, Clone, DekuRead, DekuWrite)]
+pub struct Vernaux {
+    pub hash: u32,
+    pub flags: u16,
+    /// The version index symbols refer to it by
+    pub other: u16,
+    /// Offset in the string table
+    pub name: u32,
+    /// Offset of the next `Vernaux`, from the start of this entry, or zero
+    pub next: u32,
+}
\ No newline at end of file
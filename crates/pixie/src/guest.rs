@@ -0,0 +1,411 @@
+use crate::{
+    ceil,
+    check_crc32,
+    crc32,
+    Codec,
+    CompressedResource,
+    ElfMachine,
+    Encryption,
+    Filter,
+    FilterMode,
+    FormatVersion,
+    ManifestString,
+    Object,
+    PixieError,
+    ProgramHeader,
+    SegmentType,
+};
+use alloc::{
+    borrow::Cow,
+    format,
+    vec::Vec,
+};
+use deku::prelude::*;
+use encore::env::Env;
+
+/// A part of a guest executable, compressed on its own so it can be
+/// decompressed straight to where it's needed
+#[derive(Debug, Clone, DekuRead, DekuWrite)]
+pub struct Piece {
+    /// Where the compressed bytes start, relative to the start of the
+    /// guest's pieces
+    #[deku(bytes = 8)]
+    pub offset: usize,
+    #[deku(bytes = 8)]
+    pub compressed_len: usize,
+    #[deku(bytes = 8)]
+    pub uncompressed_len: usize,
+    /// CRC-32 of the original bytes, before filtering
+    pub uncompressed_crc32: u32,
+    /// Applied before compressing
+    pub filter: Filter,
+}
+
+impl Piece {
+    /// Compresses `data` with `codec`, appending the result to `pieces`
+    fn compress(codec: Codec, data: &[u8], pieces: &mut Vec<u8>) -> Self {
+        let compressed = codec.compress_block(data);
+        Self::append(Filter::None, data, compressed, pieces)
+    }
+
+    /// Compresses `data` with `codec` after filtering it with `filter`,
+    /// appending the result to `pieces`
+    fn compress_with(codec: Codec, filter: Filter, data: &[u8], pieces: &mut Vec<u8>) -> Self {
+        let compressed = codec.compress_block(&filter.encoded(data));
+        Self::append(filter, data, compressed, pieces)
+    }
+
+    /// Stores `data` as is, in pages of its own, at an offset that matches
+    /// `vaddr` within a page, so it can be mapped straight from the packed
+    /// executable (see `Guest::segment_file_offset`)
+    fn store_mappable(data: &[u8], vaddr: u64, pieces: &mut Vec<u8>) -> Self {
+        let start = ceil(pieces.len() as u64) + (vaddr & 0xFFF);
+        pieces.resize(start as usize, 0);
+        let piece = Self::append(Filter::None, data, data.to_vec(), pieces);
+        // nothing else should end up mapped along with it
+        pieces.resize(ceil(pieces.len() as u64) as usize, 0);
+        piece
+    }
+
+    /// Compresses `data` with `codec`, both as is and filtered with
+    /// `filter`, and appends whichever is smaller to `pieces`. Returns
+    /// compressed sizes for both.
+    fn compress_filtered(
+        codec: Codec,
+        filter: Filter,
+        data: &[u8],
+        pieces: &mut Vec<u8>,
+    ) -> (Self, FilterStats) {
+        let unfiltered = codec.compress_block(data);
+        let filtered = codec.compress_block(&filter.encoded(data));
+        let use_filtered = filtered.len() < unfiltered.len();
+        let stats = FilterStats {
+            segments: 1,
+            filtered_segments: use_filtered as usize,
+            unfiltered_len: unfiltered.len(),
+            filtered_len: filtered.len(),
+        };
+
+        let piece = if use_filtered {
+            Self::append(filter, data, filtered, pieces)
+        } else {
+            Self::append(Filter::None, data, unfiltered, pieces)
+        };
+        (piece, stats)
+    }
+
+    /// Appends `compressed`, which is `data` filtered with `filter` then
+    /// compressed, to `pieces`
+    fn append(filter: Filter, data: &[u8], compressed: Vec<u8>, pieces: &mut Vec<u8>) -> Self {
+        let piece = Self {
+            offset: pieces.len(),
+            compressed_len: compressed.len(),
+            uncompressed_len: data.len(),
+            uncompressed_crc32: crc32(data),
+            filter,
+        };
+        pieces.extend(compressed);
+        piece
+    }
+
+    /// Returns the piece's compressed bytes, from `pieces`
+    fn compressed<'a>(&self, pieces: &'a [u8]) -> Result<&'a [u8], PixieError> {
+        self.offset
+            .checked_add(self.compressed_len)
+            .and_then(|end| pieces.get(self.offset..end))
+            .ok_or(PixieError::PieceOutOfRange {
+                offset: self.offset,
+                len: self.compressed_len,
+                available: pieces.len(),
+            })
+    }
+
+    /// Checks a piece that's stored as is, before it's mapped straight from
+    /// the packed executable (see `Guest::segment_file_offset`)
+    pub fn check_stored(&self, pieces: &[u8]) -> Result<(), PixieError> {
+        check_crc32("piece", self.compressed(pieces)?, self.uncompressed_crc32)
+    }
+
+    /// Decompresses the piece from `pieces` into `output`, which must be
+    /// exactly as large as the original, and checks the result.
+    pub fn decompress_into(
+        &self,
+        codec: Codec,
+        pieces: &[u8],
+        output: &mut [u8],
+    ) -> Result<(), PixieError> {
+        let compressed = self.compressed(pieces)?;
+        if output.len() != self.uncompressed_len {
+            return Err(PixieError::GuestLayoutMismatch);
+        }
+
+        codec.decompress_into(compressed, output)?;
+        self.filter.decode(output);
+        check_crc32("piece", output, self.uncompressed_crc32)
+    }
+
+    /// Decompresses the piece from `pieces`, and checks the result
+    pub fn decompress(&self, codec: Codec, pieces: &[u8]) -> Result<Vec<u8>, PixieError> {
+        let mut output = alloc::vec![0u8; self.uncompressed_len];
+        self.decompress_into(codec, pieces, &mut output)?;
+        Ok(output)
+    }
+}
+
+/// How much filtering helped, see `GuestLayout::compress`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FilterStats {
+    /// Number of filterable segments
+    pub segments: usize,
+    /// Number of those that were filtered, because they compressed better
+    /// that way. Filtering is decided for each segment on its own.
+    pub filtered_segments: usize,
+    /// Compressed size of the filterable segments, unfiltered
+    pub unfiltered_len: usize,
+    /// Compressed size of the same segments, filtered
+    pub filtered_len: usize,
+}
+
+impl core::ops::AddAssign for FilterStats {
+    fn add_assign(&mut self, other: Self) {
+        self.segments += other.segments;
+        self.filtered_segments += other.filtered_segments;
+        self.unfiltered_len += other.unfiltered_len;
+        self.filtered_len += other.filtered_len;
+    }
+}
+
+/// How a guest executable is split into pieces
+#[derive(Debug, Clone, DekuRead, DekuWrite)]
+pub struct GuestLayout {
+    /// The ELF header and program headers
+    pub headers: Piece,
+    segment_count: u16,
+    /// The file contents of each load segment, in program header order
+    #[deku(count = "segment_count")]
+    pub segments: Vec<Piece>,
+    /// The whole executable, with the contents of load segments zeroed out.
+    /// Only needed to get the original executable back: it holds section
+    /// headers, debug info, etc.
+    pub rest: Piece,
+}
+
+impl GuestLayout {
+    /// Splits `executable` into pieces and compresses each of them with
+    /// `codec`. Executable load segments of x86-64 executables are also
+    /// filtered, as `filter_mode` says. Returns the layout, the pieces (back
+    /// to back), and how much filtering helped (only measured in
+    /// `FilterMode::Auto`).
+    ///
+    /// With `Codec::Store`, load segments are never filtered, and they're
+    /// laid out so they can be mapped straight from the packed executable,
+    /// provided the pieces start on a page boundary in it.
+    pub fn compress(
+        executable: &[u8],
+        codec: Codec,
+        filter_mode: FilterMode,
+    ) -> Result<(Self, Vec<u8>, FilterStats), PixieError> {
+        let obj = Object::new(executable)?;
+        let header = obj.header();
+
+        let mut pieces = Vec::new();
+        let headers = executable
+            .get(..obj.headers_len())
+            .ok_or(PixieError::GuestLayoutMismatch)?;
+        let headers = Piece::compress(codec, headers, &mut pieces);
+
+        let mut segments = Vec::new();
+        let mut stats = FilterStats::default();
+        for seg in obj.segments().of_type(SegmentType::Load) {
+            let ph = seg.header();
+            let is_code = ph.flags & ProgramHeader::EXECUTE != 0;
+            let filter = Filter::X86 { ip: ph.vaddr };
+            let filterable = is_code && header.machine == ElfMachine::X86_64;
+            match filter_mode {
+                _ if codec == Codec::Store => {
+                    segments.push(Piece::store_mappable(seg.slice(), ph.vaddr, &mut pieces));
+                }
+                FilterMode::Auto if filterable => {
+                    let (piece, seg_stats) =
+                        Piece::compress_filtered(codec, filter, seg.slice(), &mut pieces);
+                    segments.push(piece);
+                    stats += seg_stats;
+                }
+                FilterMode::X86 if filterable => {
+                    segments.push(Piece::compress_with(codec, filter, seg.slice(), &mut pieces));
+                }
+                _ => segments.push(Piece::compress(codec, seg.slice(), &mut pieces)),
+            }
+        }
+        let rest = Piece::compress(
+            Self::rest_codec(codec),
+            &obj.without_load_segments(),
+            &mut pieces,
+        );
+
+        let layout = Self {
+            headers,
+            segment_count: segments.len() as _,
+            segments,
+            rest,
+        };
+        Ok((layout, pieces, stats))
+    }
+
+    /// Returns the codec `compress` uses for the `rest` piece, given the one
+    /// it uses for the others. Nothing ever maps `rest`, so it's compressed
+    /// even when the other pieces are stored.
+    pub fn rest_codec(codec: Codec) -> Codec {
+        match codec {
+            Codec::Store => Codec::Deflate,
+            codec => codec,
+        }
+    }
+
+    /// Puts the original executable back together from `pieces`, where the
+    /// `rest` piece was compressed with `rest_codec` and the others with
+    /// `codec`
+    pub fn reassemble(
+        &self,
+        codec: Codec,
+        rest_codec: Codec,
+        pieces: &[u8],
+    ) -> Result<Vec<u8>, PixieError> {
+        let headers = self.headers.decompress(codec, pieces)?;
+        let obj = Object::from_headers(&headers)?;
+
+        let mut executable = self.rest.decompress(rest_codec, pieces)?;
+        let load_segs = obj.segments().of_type(SegmentType::Load).collect::<Vec<_>>();
+        if load_segs.len() != self.segments.len() {
+            return Err(PixieError::GuestLayoutMismatch);
+        }
+        for (seg, piece) in load_segs.iter().zip(&self.segments) {
+            let range = seg.header().file_range();
+            let dst = executable
+                .get_mut(range.start as usize..range.end as usize)
+                .ok_or(PixieError::GuestLayoutMismatch)?;
+            piece.decompress_into(codec, pieces, dst)?;
+        }
+        Ok(executable)
+    }
+}
+
+/// A guest executable. Stage2 only decompresses its headers and load
+/// segments, straight into the guest's mappings.
+#[derive(Debug, Clone, DekuRead, DekuWrite)]
+#[deku(ctx = "minor: u16", ctx_default = "FormatVersion::CURRENT.minor")]
+pub struct Guest {
+    /// The program name that selects this guest
+    pub name: ManifestString,
+    /// How the pieces are encrypted at rest (as a whole), if at all
+    pub encryption: Encryption,
+    /// Where the pieces are stored. The uncompressed checksum is that of the
+    /// whole executable.
+    pub resource: CompressedResource,
+    /// How the executable is split into pieces. `None` for legacy guests,
+    /// which are stored as a whole instead.
+    pub layout: Option<GuestLayout>,
+    /// Whether stage2 applies the guest's relocations before running it, for
+    /// relocatable guests without an interpreter that don't relocate
+    /// themselves. Since 1.3.
+    #[deku(cond = "minor >= 3", default = "false")]
+    pub relocate: bool,
+    /// The codec the layout's `rest` piece is compressed with, see
+    /// `GuestLayout::rest_codec`. Since 1.4, the guest's codec before.
+    #[deku(cond = "minor >= 4", default = "resource.codec")]
+    pub rest_codec: Codec,
+}
+
+impl Guest {
+    /// Describes a guest from a 1.0 or 1.1 manifest: a single, nameless
+    /// executable, compressed as a whole. Those can only be unpacked.
+    pub(crate) fn legacy(resource: CompressedResource, encryption: Encryption) -> Self {
+        Self {
+            name: ManifestString::default(),
+            encryption,
+            rest_codec: resource.codec,
+            resource,
+            layout: None,
+            relocate: false,
+        }
+    }
+
+    /// Returns how the executable is split into pieces, which legacy guests
+    /// don't have
+    pub fn layout(&self) -> Result<&GuestLayout, PixieError> {
+        self.layout.as_ref().ok_or(PixieError::LegacyGuest)
+    }
+
+    /// Returns the guest's pieces, checked and decrypted. If they're
+    /// encrypted, `env` is used to find the key.
+    pub fn pieces<'a>(
+        &self,
+        full_slice: &'a [u8],
+        env: &Env,
+    ) -> Result<Cow<'a, [u8]>, PixieError> {
+        let stored = self.resource.stored(full_slice)?;
+        match &self.encryption {
+            Encryption::None => Ok(Cow::Borrowed(stored)),
+            encryption => Ok(Cow::Owned(encryption.decrypt(stored, env)?)),
+        }
+    }
+
+    /// Like `pieces`, for stage2. If segments may be mapped straight from
+    /// the packed executable (see `segment_file_offset`), the pieces aren't
+    /// checked as a whole but one by one: as they're decompressed, or with
+    /// `Piece::check_stored` before they're mapped.
+    pub fn pieces_to_map<'a>(
+        &self,
+        full_slice: &'a [u8],
+        env: &Env,
+    ) -> Result<Cow<'a, [u8]>, PixieError> {
+        if self.is_mappable() {
+            let stored = self.resource.resource.slice("guest", full_slice)?;
+            return Ok(Cow::Borrowed(stored));
+        }
+        self.pieces(full_slice, env)
+    }
+
+    /// Returns whether some segments may be mapped straight from the packed
+    /// executable: the pieces have to be stored as is, unencrypted
+    fn is_mappable(&self) -> bool {
+        let unencrypted = matches!(self.encryption, Encryption::None);
+        self.resource.codec == Codec::Store && unencrypted
+    }
+
+    /// Returns where the contents of the `i`th load segment of `obj` (the
+    /// guest, as read from its headers) start in the packed executable, if
+    /// they can be mapped straight from it: they have to be stored as is,
+    /// unencrypted, at an offset that matches the segment's address within
+    /// a page, and the segment can't share pages with another one.
+    pub fn segment_file_offset(&self, obj: &Object, i: usize) -> Option<u64> {
+        if self.is_mappable() == false {
+            return None;
+        }
+
+        let piece = self.layout.as_ref()?.segments.get(i)?;
+        let seg = obj.segments().of_type(SegmentType::Load).nth(i)?;
+        let ph = seg.header();
+        let offset = self.resource.resource.offset as u64 + piece.offset as u64;
+        let mappable = piece.filter == Filter::None
+            && piece.compressed_len as u64 == ph.file_size
+            && offset & 0xFFF == ph.vaddr & 0xFFF
+            && obj.segments().shares_pages(seg) == false;
+        mappable.then(|| offset)
+    }
+
+    /// Returns the original executable, checked, decrypted and reassembled.
+    /// If it's encrypted, `env` is used to find the key.
+    pub fn load(&self, full_slice: &[u8], env: &Env) -> Result<Vec<u8>, PixieError> {
+        let layout = match &self.layout {
+            Some(layout) => layout,
+            None => return self.resource.load(full_slice, &self.encryption, env),
+        };
+        let pieces = self.pieces(full_slice, env)?;
+        let executable = layout.reassemble(self.resource.codec, self.rest_codec, &pieces)?;
+        if let Encryption::None = self.encryption {
+            check_crc32("uncompressed", &executable, self.resource.uncompressed_crc32)?;
+        }
+        Ok(executable)
+    }
+}
\ No newline at end of file
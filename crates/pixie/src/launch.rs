@@ -1,13 +1,82 @@
 use crate::syscall;
 use core::arch::asm;
+use encore::{
+    env::Env,
+    syscall::FileDescriptor,
+};
+
+/// What stage1 hands stage2 besides the stack: the packed executable, as
+/// stage1 opened, mapped and checked it. stage2 reads from it instead of
+/// opening `/proc/self/exe` again, which nothing would have checked.
+#[repr(C)]
+pub struct PackedExe {
+    fd: u64,
+    ptr: *const u8,
+    len: usize,
+}
+
+impl PackedExe {
+    pub fn new(fd: FileDescriptor, full_slice: &[u8]) -> Self {
+        Self {
+            fd: fd.0,
+            ptr: full_slice.as_ptr(),
+            len: full_slice.len(),
+        }
+    }
+
+    /// Returns the file descriptor the packed executable was opened as
+    pub fn fd(&self) -> FileDescriptor {
+        FileDescriptor(self.fd)
+    }
+
+    /// Returns the packed executable, as mapped by stage1
+    ///
+    /// # Safety
+    /// The mapping `self` was made from must still be around.
+    pub unsafe fn full_slice(&self) -> &[u8] {
+        core::slice::from_raw_parts(self.ptr, self.len)
+    }
+}
 
 /// # Safety
 /// Nothing about this function is safe.
-#[inline(never)]
 pub unsafe fn launch(stack_top: *mut u8, entry_point: u64) -> ! {
+    jump(stack_top, entry_point, &[])
+}
+
+/// Like `launch`, but hands `env` (args, environment variables and aux
+/// vectors) over to the entry point instead of what's on the stack.
+///
+/// # Safety
+/// Nothing about this function is safe either.
+pub unsafe fn launch_with_env(stack_top: *mut u8, entry_point: u64, env: &Env) -> ! {
+    // The new block goes right below the old one, whose strings it still
+    // points to. Entry points expect a 16-byte aligned stack.
+    let block = env.to_stack();
+    let new_top = ((stack_top as u64 - block.len() as u64) & !0xF) as *mut u8;
+    jump(new_top, entry_point, &block)
+}
+
+/// Copies `block` at `stack_top`, then jumps to `entry_point` with the stack
+/// pointer set to `stack_top`.
+///
+/// # Safety
+/// Our own stack frames may be where `block` goes, so the copy is done in
+/// assembly, after which nothing but registers is used.
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+unsafe fn jump(stack_top: *mut u8, entry_point: u64, block: &[u8]) -> ! {
     // handy for breakpoints
     syscall::dup(0);
     asm!(
+        /////////////////////////////////
+        // Copy the new top of the stack, if any
+        /////////////////////////////////
+
+        "mov rdi, r12",
+        "cld",
+        "rep movsb",
+
         /////////////////////////////////
         // Clear some of the stack
         /////////////////////////////////
@@ -55,6 +124,155 @@ pub unsafe fn launch(stack_top: *mut u8, entry_point: u64) -> ! {
 
         in("r12") stack_top,
         in("r13") entry_point,
+        in("rsi") block.as_ptr(),
+        in("rcx") block.len(),
+        options(noreturn)
+    )
+}
+
+/// See the x86_64 version. `esi` can't be an operand, so the source of the
+/// copy is passed in `eax` instead.
+///
+/// # Safety
+/// Same as the x86_64 version.
+#[cfg(target_arch = "x86")]
+#[inline(never)]
+unsafe fn jump(stack_top: *mut u8, entry_point: u64, block: &[u8]) -> ! {
+    // handy for breakpoints
+    syscall::dup(0);
+    asm!(
+        /////////////////////////////////
+        // Copy the new top of the stack, if any
+        /////////////////////////////////
+
+        "mov esi, eax",
+        "mov edi, edx",
+        "cld",
+        "rep movsb",
+
+        /////////////////////////////////
+        // Clear some of the stack
+        /////////////////////////////////
+
+        "mov esi, edx",
+        "sub esi, 0x1000",
+
+        "$clear_stack:",
+            "cmp esi, edx",
+            "je $clear_stack_done",
+            "mov dword ptr [esi], 0",
+            "add esi, 0x4",
+            "jmp $clear_stack",
+
+        "$clear_stack_done:",
+
+        /////////////////////////////////
+        // Set up stack pointer
+        /////////////////////////////////
+
+        "mov esp, edx",
+
+        /////////////////////////////////
+        // Jump to the entry point
+        /////////////////////////////////
+
+        // Clear everything that isn't ebx, like the kernel does
+        // https://elixir.bootlin.com/linux/latest/source/arch/x86/include/asm/elf.h#L102
+        "xor eax, eax",
+        "xor ecx, ecx",
+        "xor edx, edx",
+        "xor esi, esi",
+        "xor edi, edi",
+        "xor ebp, ebp",
+
+        // Now we can actually jump to the entry point
+        "jmp ebx",
+
+        in("edx") stack_top,
+        in("ebx") entry_point as usize,
+        in("eax") block.as_ptr(),
+        in("ecx") block.len(),
+        options(noreturn)
+    )
+}
+
+/// See the x86_64 version.
+///
+/// # Safety
+/// Same as the x86_64 version.
+#[cfg(target_arch = "aarch64")]
+#[inline(never)]
+unsafe fn jump(stack_top: *mut u8, entry_point: u64, block: &[u8]) -> ! {
+    // handy for breakpoints
+    syscall::dup(0);
+    asm!(
+        /////////////////////////////////
+        // Copy the new top of the stack, if any
+        /////////////////////////////////
+
+        "mov x13, x9",
+        "1:",
+            "cbz x12, 2f",
+            "ldrb w14, [x11], #1",
+            "strb w14, [x13], #1",
+            "sub x12, x12, #1",
+            "b 1b",
+        "2:",
+
+        /////////////////////////////////
+        // Clear some of the stack
+        /////////////////////////////////
+
+        // 0x1000 doesn't fit in an immediate, shift it
+        "sub x13, x9, #1, lsl #12",
+        "3:",
+            "cmp x13, x9",
+            "b.eq 4f",
+            "str xzr, [x13], #8",
+            "b 3b",
+        "4:",
+
+        /////////////////////////////////
+        // Set up stack pointer
+        /////////////////////////////////
+
+        "mov sp, x9",
+
+        /////////////////////////////////
+        // Jump to the entry point
+        /////////////////////////////////
+
+        // Clear everything that isn't x10, like the kernel does (it clears
+        // all of them). In particular, `x0` is a function the program should
+        // register with `atexit`, if not null.
+        "mov x0, xzr",
+        "mov x1, xzr",
+        "mov x2, xzr",
+        "mov x3, xzr",
+        "mov x4, xzr",
+        "mov x5, xzr",
+        "mov x6, xzr",
+        "mov x7, xzr",
+        "mov x8, xzr",
+        "mov x9, xzr",
+        // skip x10, we have the entry point in there
+        "mov x11, xzr",
+        "mov x12, xzr",
+        "mov x13, xzr",
+        "mov x14, xzr",
+        "mov x15, xzr",
+        "mov x16, xzr",
+        "mov x17, xzr",
+        "mov x29, xzr",
+        "mov x30, xzr",
+
+        // Now we can actually jump to the entry point
+        "br x10",
+
+        in("x9") stack_top,
+        in("x10") entry_point,
+        in("x11") block.as_ptr(),
+        in("x12") block.len(),
         options(noreturn)
     )
 }
\ No newline at end of file
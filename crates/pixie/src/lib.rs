@@ -4,14 +4,37 @@ extern crate alloc;
 
 pub use deku;
 use deku::prelude::*;
+use deku::bitvec::BitView;
 use encore::prelude::*;
+use encore::memmap::{
+    self,
+    FileOpts,
+};
 
 mod manifest;
 pub use manifest::*;
 
+mod guest;
+pub use guest::*;
+
+mod codec;
+pub use codec::*;
+
+mod filter;
+pub use filter::*;
+
+mod crc32;
+pub use crc32::*;
+
+mod crypto;
+pub use crypto::*;
+
 mod writer;
 pub use writer::*;
 
+mod builder;
+pub use builder::*;
+
 mod format;
 pub use format::*;
 
@@ -39,6 +62,116 @@ pub enum PixieError {
     DynamicEntryNotFound(DynamicTagType),
     /// unsupported relocation type `{0:?}`
     UnsupportedRela(Rela),
+    /// could not decompress `{0:?}` payload
+    DecompressionFailed(Codec),
+    /// payload is corrupted: {what} checksum is 0x{actual:08x}, expected 0x{expected:08x}
+    ChecksumMismatch {
+        what: &'static str,
+        expected: u32,
+        actual: u32,
+    },
+    /// invalid key, expected 64 hexadecimal digits
+    InvalidKey,
+    /// packed executable is not signed
+    MissingSignature,
+    /// signature verification failed
+    BadSignature,
+    /// environment variable `{0}`, which should hold the decryption key, is not set
+    KeyVarMissing(String),
+    /// could not encrypt payload
+    EncryptionFailed,
+    /// could not decrypt payload: wrong key, or corrupted data
+    DecryptionFailed,
+    /// unsupported manifest format version {0}
+    UnsupportedManifestVersion(FormatVersion),
+    /// manifest is missing its `{0:?}` record
+    MissingManifestRecord(RecordType),
+    /// manifest has a malformed signature record
+    InvalidSignatureRecord,
+    /// no end marker found: not a file produced by minipak, or truncated
+    MissingEndMarker,
+    /// manifest data at 0x{offset:x} is out of range for 0x{file_len:x} bytes
+    ManifestOutOfRange { offset: usize, file_len: usize },
+    /// {what} (0x{len:x} bytes at 0x{offset:x}) is out of range for a file of 0x{file_len:x} bytes
+    ResourceOutOfRange {
+        what: &'static str,
+        offset: usize,
+        len: usize,
+        file_len: usize,
+    },
+    /// no guest named `{0}` in this packed executable
+    GuestNotFound(String),
+    /// guest piece (0x{len:x} bytes at 0x{offset:x}) is out of range for 0x{available:x} bytes of pieces
+    PieceOutOfRange {
+        offset: usize,
+        len: usize,
+        available: usize,
+    },
+    /// guest pieces don't match its program headers
+    GuestLayoutMismatch,
+    /// no object to copy symbol `{0}` from, for a copy relocation
+    CopySourceNotFound(String),
+    /// symbol `{0}` not found
+    SymbolNotFound(String),
+    /// malformed symbol hash table
+    MalformedHashTable,
+    /// malformed symbol version tables
+    MalformedVersions,
+    /// malformed string table
+    MalformedStringTable,
+    /// `{0:?}` dynamic entry points outside the object
+    DynamicEntryOutOfRange(DynamicTagType),
+    /// could not find section `{0}`
+    SectionNotFound(String),
+    /// section headers point outside the file
+    MalformedSections,
+    /// malformed note
+    MalformedNotes,
+    /// invalid ELF layout: {0}
+    InvalidElfLayout(&'static str),
+    /// notes don't fit between the program headers and the next load segment
+    NotesDontFit,
+    /// ifunc resolvers can only run in an object relocated for where it's mapped
+    IRelativeElsewhere,
+    /// addresses 0x{0:x}..0x{1:x} are outside the mapped object
+    AddressOutOfRange(u64, u64),
+    /// interpreter path is not valid UTF-8
+    InvalidInterpreterPath,
+    /// interpreter `{0}` is not relocatable
+    InterpreterNotRelocatable(String),
+    /// string of {0} bytes is too long for the manifest, which allows 65535
+    StringTooLong(usize),
+    /// guest was packed by an older minipak, it can only be unpacked
+    LegacyGuest,
+}
+
+impl PixieError {
+    /// Returns the exit status stage1 and stage2 use when they bail out
+    /// because of this error, so that callers can tell failures apart:
+    ///
+    ///   * 2: the packed executable is malformed or truncated
+    ///   * 3: a checksum or signature doesn't match
+    ///   * 4: the decryption key is missing or wrong
+    ///   * 1: anything else
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::MissingEndMarker
+            | Self::ManifestOutOfRange { .. }
+            | Self::ResourceOutOfRange { .. }
+            | Self::UnsupportedManifestVersion(_)
+            | Self::MissingManifestRecord(_)
+            | Self::InvalidSignatureRecord
+            | Self::PieceOutOfRange { .. }
+            | Self::GuestLayoutMismatch
+            | Self::Deku(_) => 2,
+            Self::ChecksumMismatch { .. }
+            | Self::MissingSignature
+            | Self::BadSignature
+            | Self::DecompressionFailed(_) => 3,
+            Self::InvalidKey | Self::KeyVarMissing(_) | Self::DecryptionFailed => 4,
+            _ => 1,
+        }
+    }
 }
 
 impl From<DekuError> for PixieError {
@@ -105,6 +238,16 @@ impl<'a> Segments<'a> {
             .ok_or(PixieError::SegmentNotFound(typ))
     }
 
+    /// Returns true if any of the pages `seg` spans in memory also holds
+    /// another load segment
+    pub fn shares_pages(&self, seg: &Segment) -> bool {
+        let pages = align_hull(seg.header().mem_range());
+        self.of_type(SegmentType::Load)
+            .filter(|other| core::ptr::eq(*other, seg) == false)
+            .map(|other| align_hull(other.header().mem_range()))
+            .any(|other| other.start < pages.end && pages.start < other.end)
+    }
+
     /// Returns a 4K-aligned convex hull of all the load segments
     pub fn load_convex_hull(&self) -> Result<Range<u64>, PixieError> {
         self.of_type(SegmentType::Load)
@@ -119,22 +262,153 @@ impl<'a> Segments<'a> {
     }
 }
 
+pub struct Section<'a> {
+    header: SectionHeader,
+    name: &'a str,
+    slice: &'a [u8],
+}
+
+impl<'a> Section<'a> {
+    pub fn typ(&self) -> SectionType {
+        self.header.r#type
+    }
+
+    pub fn header(&self) -> &SectionHeader {
+        &self.header
+    }
+
+    /// Returns the section's name, as found in `.shstrtab`
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    pub fn slice(&self) -> &'a [u8] {
+        self.slice
+    }
+}
+
+#[derive(Default)]
+pub struct Sections<'a> {
+    sections: Vec<Section<'a>>,
+}
+
+impl<'a> Sections<'a> {
+    /// Parses the section headers of the object in `full_slice`, and names
+    /// them using its `.shstrtab`
+    fn parse(header: &ObjectHeader, full_slice: &'a [u8]) -> Result<Self, PixieError> {
+        if header.sh_offset == 0 {
+            return Ok(Default::default());
+        }
+
+        let mut input = full_slice
+            .get(header.sh_offset as usize..)
+            .ok_or(PixieError::MalformedSections)?;
+        // With too many sections to fit in the object header, the real count
+        // is stored in the first section header instead
+        let count = match header.sh_count {
+            0 => SectionHeader::from_class_bytes(input, header.class)?.1.size as usize,
+            count => count as usize,
+        };
+        let mut headers: Vec<SectionHeader> = Default::default();
+        for _ in 0..count {
+            let (_, shdr) = SectionHeader::from_class_bytes(input, header.class)?;
+            headers.push(shdr);
+            input = input
+                .get(header.sh_entsize as usize..)
+                .ok_or(PixieError::MalformedSections)?;
+        }
+
+        let strtab_index = match header.sh_nidx {
+            SectionHeader::XINDEX => headers.first().map_or(0, |shdr| shdr.link as usize),
+            index => index as usize,
+        };
+        let strtab = match headers.get(strtab_index) {
+            Some(shdr) if strtab_index != 0 => section_slice(shdr, full_slice)?,
+            _ => &[],
+        };
+
+        let sections = headers
+            .into_iter()
+            .map(|header| {
+                let name = strtab
+                    .get(header.name as usize..)
+                    .map(|name| &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())])
+                    .and_then(|name| core::str::from_utf8(name).ok())
+                    .ok_or(PixieError::MalformedSections)?;
+                let slice = section_slice(&header, full_slice)?;
+                Ok(Section {
+                    header,
+                    name,
+                    slice,
+                })
+            })
+            .collect::<Result<_, PixieError>>()?;
+        Ok(Self { sections })
+    }
+
+    pub fn all(&self) -> &[Section<'a>] {
+        &self.sections
+    }
+
+    /// Returns all the sections of a given type
+    pub fn of_type(&self, typ: SectionType) -> impl Iterator<Item = &Section<'a>> + '_ {
+        self.sections
+            .iter()
+            .filter(move |&section| section.typ() == typ)
+    }
+
+    /// Returns the first section with a given name, like `.symtab`
+    pub fn by_name(&self, name: &str) -> Result<&Section<'a>, PixieError> {
+        self.sections
+            .iter()
+            .find(|section| section.name() == name)
+            .ok_or_else(|| PixieError::SectionNotFound(name.into()))
+    }
+}
+
+/// Returns the contents of the section `header` describes
+fn section_slice<'a>(header: &SectionHeader, full_slice: &'a [u8]) -> Result<&'a [u8], PixieError> {
+    let range = header.file_range();
+    full_slice
+        .get(range.start as usize..range.end as usize)
+        .ok_or(PixieError::MalformedSections)
+}
+
 pub struct Object<'a> {
     header: ObjectHeader,
     slice: &'a [u8],
     segments: Segments<'a>,
+    /// Whether `slice` holds the whole object, see `from_headers`
+    with_contents: bool,
 }
 
 impl<'a> Object<'a> {
     /// Read an ELF object from a given slice
     pub fn new(slice: &'a [u8]) -> Result<Self, PixieError> {
+        Self::parse(slice, true)
+    }
+
+    /// Read only the headers of an ELF object, from a slice that may end
+    /// right after its program headers. All segments have empty slices.
+    pub fn from_headers(slice: &'a [u8]) -> Result<Self, PixieError> {
+        Self::parse(slice, false)
+    }
+
+    fn parse(slice: &'a [u8], with_contents: bool) -> Result<Self, PixieError> {
         let (_, header) = ObjectHeader::from_bytes((slice, 0))?;
         let segments = {
             let mut result = Segments::default();
-            let mut segs_input = (&slice[header.ph_offset as usize..], 0);
+            let mut segs_input = &slice[header.ph_offset as usize..];
             for _ in 0..header.ph_count {
-                let (rest, phed) = ProgramHeader::from_bytes(segs_input)?;
-                result.segments.push(Segment::new(phed, slice));
+                let (rest, phed) = ProgramHeader::from_class_bytes(segs_input, header.class)?;
+                result.segments.push(if with_contents {
+                    Segment::new(phed, slice)
+                } else {
+                    Segment {
+                        header: phed,
+                        slice: &[],
+                    }
+                });
                 segs_input = rest;
             }
 
@@ -145,26 +419,14 @@ impl<'a> Object<'a> {
             slice,
             header,
             segments,
+            with_contents,
          })
     }
 
     /// Read all dynamic entries
     pub fn read_dynamic_entries(&self) -> Result<DynamicEntries<'a>, PixieError> {
         let dyn_seg = self.segments.find(SegmentType::Dynamic)?;
-        let mut entries = DynamicEntries::default();
-        let mut input = (dyn_seg.slice(), 0);
-        loop {
-            let (rest, tag) = DynamicTag::from_bytes(input)?;
-            if tag.typ == DynamicTagType::Null {
-                break;
-            }
-            entries.items.push(DynamicEntry {
-                tag,
-                full_slice: &self.slice,
-            });
-            input = rest;
-        }
-        Ok(entries)
+        DynamicEntries::parse(dyn_seg.slice, self.slice, self.header.class)
     }
 
     /// Returns the ELF object header
@@ -181,15 +443,167 @@ impl<'a> Object<'a> {
     pub fn segments(&self) -> &Segments {
         &self.segments
     }
+
+    /// Returns every note in the object's `PT_NOTE` segments
+    pub fn notes(&self) -> Result<Vec<Note<'a>>, PixieError> {
+        let mut notes = Vec::new();
+        for seg in self.segments.of_type(SegmentType::Note) {
+            notes.extend(Note::parse_all(seg.slice, seg.header().align)?);
+        }
+        Ok(notes)
+    }
+
+    /// Returns the object's GNU build ID note, if it has one
+    pub fn build_id(&self) -> Result<Option<Note<'a>>, PixieError> {
+        Ok(self.notes()?.into_iter().find(|note| note.is_build_id()))
+    }
+
+    /// Like `DynamicEntries::syms`, but symbols can be counted (and iterated
+    /// over) without a hash table too, if the object has a `.dynsym` section
+    /// header.
+    pub fn syms<'b>(&self, dyn_entries: &'b DynamicEntries<'b>) -> Result<Syms<'b>, PixieError> {
+        let mut syms = dyn_entries.syms()?;
+        if matches!(syms.index, SymIndex::None) {
+            let sections = self.sections()?;
+            if let Some(dynsym) = sections.of_type(SectionType::DynSym).next() {
+                let header = dynsym.header();
+                let count = header.size.checked_div(header.entsize);
+                syms.count = Some(count.ok_or(PixieError::MalformedSections)? as usize);
+            }
+        }
+        Ok(syms)
+    }
+
+    /// Parses all the object's sections. Empty for stripped objects, and
+    /// objects read with `from_headers`. Running an object doesn't need
+    /// them, so they're only parsed (and checked) here.
+    pub fn sections(&self) -> Result<Sections<'a>, PixieError> {
+        if self.with_contents == false {
+            return Ok(Default::default());
+        }
+        Sections::parse(&self.header, self.slice)
+    }
+
+    /// Returns how many bytes the ELF header and program headers span, from
+    /// the start of the file
+    pub fn headers_len(&self) -> usize {
+        self.header.ph_offset as usize
+            + self.header.ph_count as usize * self.header.ph_entsize as usize
+    }
+
+    /// Returns a copy of the object with only what's needed to run it: the
+    /// ELF header, the program headers and the contents of the segments, at
+    /// their original offsets. Section headers are dropped, and everything
+    /// else (symbols, debug info...) is zeroed out or cut off.
+    pub fn stripped(&self) -> Result<Vec<u8>, PixieError> {
+        let headers_len = self.headers_len();
+        let len = self
+            .segments
+            .all()
+            .iter()
+            .map(|seg| seg.header().file_range().end as usize)
+            .fold(headers_len, core::cmp::max);
+
+        let mut output = alloc::vec![0u8; len];
+        output[..headers_len].copy_from_slice(&self.slice[..headers_len]);
+        for seg in self.segments.all() {
+            let range = seg.header().file_range();
+            output[range.start as usize..range.end as usize].copy_from_slice(seg.slice());
+        }
+
+        let header = ObjectHeader {
+            sh_offset: 0,
+            sh_count: 0,
+            sh_nidx: 0,
+            ..self.header.clone()
+        };
+        let header = header.to_bytes()?;
+        output[..header.len()].copy_from_slice(&header);
+        Ok(output)
+    }
+
+    /// Returns a copy of the object with the contents of its load segments
+    /// zeroed out: whatever's left is only needed to get the original file
+    /// back (section headers, symbols, debug info...).
+    pub fn without_load_segments(&self) -> Vec<u8> {
+        let mut output = self.slice.to_vec();
+        for seg in self.segments.of_type(SegmentType::Load) {
+            let range = seg.header().file_range();
+            output[range.start as usize..range.end as usize].fill(0);
+        }
+        output
+    }
+
+    /// Returns a copy of the object for debuggers only, like the one
+    /// `objcopy --only-keep-debug` writes: the contents of load segments
+    /// are zeroed out, and the sections they held are marked `NOBITS`. The
+    /// ELF header, program headers, section headers, notes (so the build ID
+    /// still matches), symbols and debug info are kept.
+    pub fn debug_only(&self) -> Result<Vec<u8>, PixieError> {
+        let mut output = self.without_load_segments();
+        let headers_len = self.headers_len();
+        output[..headers_len].copy_from_slice(&self.slice[..headers_len]);
+
+        // the section header table is usually past the load segments, but
+        // it doesn't have to be
+        let sections = self.sections()?;
+        let sh_table = self.header.sh_offset as usize
+            ..self.header.sh_offset as usize
+                + sections.all().len() * self.header.sh_entsize as usize;
+        output[sh_table.clone()].copy_from_slice(&self.slice[sh_table.clone()]);
+
+        for (i, section) in sections.all().iter().enumerate() {
+            let range = section.header().file_range();
+            let loaded = self.segments.of_type(SegmentType::Load).any(|seg| {
+                let seg_range = seg.header().file_range();
+                range.start < seg_range.end && seg_range.start < range.end
+            });
+            if loaded == false {
+                continue;
+            }
+
+            if section.typ() == SectionType::Note {
+                output[range.start as usize..range.end as usize].copy_from_slice(section.slice());
+                continue;
+            }
+            let header = SectionHeader {
+                r#type: SectionType::NoBits,
+                ..section.header().clone()
+            };
+            let header = header.to_class_bytes(self.header.class)?;
+            let offset = sh_table.start + i * self.header.sh_entsize as usize;
+            output[offset..][..header.len()].copy_from_slice(&header);
+        }
+        Ok(output)
+    }
 }
 
 /// Entries in the `DYNAMIC` segment.
-#[derive(Default)]
 pub struct DynamicEntries<'a> {
     items: Vec<DynamicEntry<'a>>,
+    class: ElfClass,
 }
 
 impl<'a> DynamicEntries<'a> {
+    /// Parses the contents of the `DYNAMIC` segment of an object of class
+    /// `class`. The addresses entries hold are looked up in `full_slice`.
+    fn parse(dynamic: &'a [u8], full_slice: &'a [u8], class: ElfClass) -> Result<Self, PixieError> {
+        let mut entries = DynamicEntries {
+            items: Default::default(),
+            class,
+        };
+        let mut input = dynamic;
+        loop {
+            let (rest, tag) = DynamicTag::from_class_bytes(input, class)?;
+            if tag.typ == DynamicTagType::Null {
+                break;
+            }
+            entries.items.push(DynamicEntry { tag, full_slice, class });
+            input = rest;
+        }
+        Ok(entries)
+    }
+
     /// Returns a slice of all entries
     pub fn all(&self) -> &[DynamicEntry<'a>] {
         &self.items
@@ -208,12 +622,34 @@ impl<'a> DynamicEntries<'a> {
     }
 
     /// Constructs an instance of `Syms`. Requires the presence of the `SymTab`,
-    /// `SymEnt` and `StrTab` dynamic entries.
+    /// `SymEnt`, `StrTab` and `StrSz` dynamic entries. Symbols can only be
+    /// looked up by name if there's a `GnuHash` or `Hash` entry too, and
+    /// without one, only `Object::syms` can count them.
     pub fn syms(&'a self) -> Result<Syms<'a>, PixieError> {
+        let index = if let Ok(gnu_hash) = self.find(DynamicTagType::GnuHash) {
+            let (_, table) = GnuHashTable::read(gnu_hash.as_slice()?.view_bits(), self.class)?;
+            let chains = gnu_hash
+                .as_slice()?
+                .get(table.chains_offset()..)
+                .ok_or(PixieError::MalformedHashTable)?;
+            SymIndex::Gnu { table, chains }
+        } else if let Ok(hash) = self.find(DynamicTagType::Hash) {
+            let (_, table) = HashTable::from_bytes((hash.as_slice()?, 0))?;
+            SymIndex::Sysv(table)
+        } else {
+            SymIndex::None
+        };
+
         Ok(Syms {
             symtab: self.find(DynamicTagType::SymTab)?,
             syment: self.find(DynamicTagType::SymEnt)?,
             strtab: self.find(DynamicTagType::StrTab)?,
+            strsz: self.find(DynamicTagType::StrSz)?,
+            index,
+            count: None,
+            versym: self.find(DynamicTagType::VerSym).ok(),
+            verdef: self.find(DynamicTagType::VerDef).ok(),
+            verneed: self.find(DynamicTagType::VerNeed).ok(),
         })
     }
 }
@@ -225,6 +661,9 @@ pub struct DynamicEntry<'a> {
 
     /// A slice of the full ELF object
     full_slice: &'a [u8],
+
+    /// The class of the ELF object, which records are laid out for
+    class: ElfClass,
 }
 
 impl<'a> DynamicEntry<'a> {
@@ -235,8 +674,10 @@ impl<'a> DynamicEntry<'a> {
 
     /// Returns a slice of the full file starting with this entry interpreted as
     /// an offset.
-    pub fn as_slice(&self) -> &'a [u8] {
-        &self.full_slice[self.as_usize()..]
+    pub fn as_slice(&self) -> Result<&'a [u8], PixieError> {
+        self.full_slice
+            .get(self.as_usize()..)
+            .ok_or(PixieError::DynamicEntryOutOfRange(self.typ()))
     }
 
     /// Returns this entry's value as an `usize`
@@ -254,36 +695,41 @@ impl<'a> DynamicEntry<'a> {
     pub fn parse_all<T>(
         &self,
         len: &DynamicEntry<'a>,
-    ) -> impl Iterator<Item = Result<T, PixieError>> + 'a
+    ) -> Result<impl Iterator<Item = Result<T, PixieError>> + 'a, PixieError>
     where
-        T: DekuContainerRead<'a>,
+        T: ElfRecord,
     {
-        let slice = &self.as_slice()[..len.as_usize()];
-        let mut input = (slice, 0);
-
-        core::iter::from_fn(move || -> Option<Result<T, PixieError>> {
-            if input.0.is_empty() {
+        let slice = self
+            .as_slice()?
+            .get(..len.as_usize())
+            .ok_or(PixieError::DynamicEntryOutOfRange(len.typ()))?;
+        let mut input = slice;
+        let class = self.class;
+
+        Ok(core::iter::from_fn(move || -> Option<Result<T, PixieError>> {
+            if input.is_empty() {
                 return None;
             }
 
-            let (rest, t) = match T::from_bytes(input) {
+            let (rest, t) = match T::from_class_bytes(input, class) {
                 Ok(x) => x,
                 Err(e) => return Some(Err(e.into())),
             };
             input = rest;
             Some(Ok(t))
-        })
+        }))
     }
 
     /// Parses the nth `T` record, using `self` as the start of the input, and
     /// `record_len` as the record length.
-    pub fn parse_nth<T>(&self, record_len: &DynamicEntry<'a>, n: usize) -> Result<T, DekuError>
+    pub fn parse_nth<T>(&self, record_len: &DynamicEntry<'a>, n: usize) -> Result<T, PixieError>
     where
-        T: DekuContainerRead<'a>,
+        T: ElfRecord,
     {
-        let slice = &self.as_slice()[(record_len.as_usize() * n)..];
-        let input = (slice, 0);
-        let (_, t) = T::from_bytes(input)?;
+        let out_of_range = || PixieError::DynamicEntryOutOfRange(self.typ());
+        let offset = record_len.as_usize().checked_mul(n).ok_or_else(out_of_range)?;
+        let slice = self.as_slice()?.get(offset..).ok_or_else(out_of_range)?;
+        let (_, t) = T::from_class_bytes(slice, self.class)?;
         Ok(t)
     }
 }
@@ -296,30 +742,298 @@ pub struct Syms<'a> {
     syment: &'a DynamicEntry<'a>,
     /// Indicates the start of the string table
     strtab: &'a DynamicEntry<'a>,
+    /// Indicates the size of the string table
+    strsz: &'a DynamicEntry<'a>,
+    /// Used to look symbols up by name, and to count them
+    index: SymIndex<'a>,
+    /// How many symbols there are, for symbol tables without a hash table to
+    /// count them, see `Object::syms`
+    count: Option<usize>,
+    /// Indicates the start of the version of each symbol, if versioned
+    versym: Option<&'a DynamicEntry<'a>>,
+    /// Indicates the start of the versions the object defines, if any
+    verdef: Option<&'a DynamicEntry<'a>>,
+    /// Indicates the start of the versions the object needs, if any
+    verneed: Option<&'a DynamicEntry<'a>>,
+}
+
+/// The version of a dynamic symbol, see `Syms::version`
+#[derive(Debug, Clone, Copy)]
+pub struct SymVersion<'a> {
+    pub name: &'a str,
+    /// Whether it's one of the object's own versions, rather than one it
+    /// needs from a dependency
+    pub defined: bool,
+    /// Whether it's an older version, only used when asked for by name:
+    /// `memcpy@GLIBC_2.2.5` rather than `memcpy@@GLIBC_2.14`
+    pub hidden: bool,
+}
+
+/// The hash table of a symbol table, if any
+enum SymIndex<'a> {
+    Gnu {
+        table: GnuHashTable,
+        /// The rest of the table, from the first chain entry
+        chains: &'a [u8],
+    },
+    Sysv(HashTable),
+    None,
 }
 
 impl<'a> Syms<'a> {
     /// Read the nth symbol
     pub fn nth(&self, n: usize) -> Result<(Sym, &'a str), PixieError> {
         let sym: Sym = self.symtab.parse_nth(&self.syment, n)?;
-        let name = unsafe { self.strtab.as_slice().as_ptr().add(sym.name as _).cstr() };
+        let name = self.string(sym.name)?;
         Ok((sym, name))
     }
 
-    /// Find a symbol by name. Will end up panicking if the symbol
-    /// is not found!
-    pub fn by_name(&self, name: &str) -> Result<Sym, PixieError> {
-        let mut i = 0;
+    /// Reads a null-terminated string from the string table
+    fn string(&self, offset: u32) -> Result<&'a str, PixieError> {
+        let strtab = self
+            .strtab
+            .as_slice()?
+            .get(..self.strsz.as_usize())
+            .ok_or(PixieError::DynamicEntryOutOfRange(DynamicTagType::StrSz))?;
+        let bytes = strtab.get(offset as usize..).ok_or(PixieError::MalformedStringTable)?;
+        let len = bytes
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(PixieError::MalformedStringTable)?;
+        core::str::from_utf8(&bytes[..len]).map_err(|_| PixieError::MalformedStringTable)
+    }
+
+    /// Iterates over every symbol and its name, starting with the 0th one
+    pub fn iter(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<(Sym, &'a str), PixieError>> + '_, PixieError> {
+        Ok((0..self.len()?).map(move |n| self.nth(n)))
+    }
+
+    /// Iterates over the symbols with a given binding: `SymBind::Global`
+    /// and `SymBind::Weak` ones are exported (if defined)
+    pub fn of_bind(
+        &self,
+        bind: SymBind,
+    ) -> Result<impl Iterator<Item = Result<(Sym, &'a str), PixieError>> + '_, PixieError> {
+        Ok(self
+            .iter()?
+            .filter(move |res| res.as_ref().map_or(true, |(sym, _)| sym.bind == bind)))
+    }
+
+    /// Iterates over the symbols of a given type, e.g. `SymType::Func`
+    pub fn of_type(
+        &self,
+        typ: SymType,
+    ) -> Result<impl Iterator<Item = Result<(Sym, &'a str), PixieError>> + '_, PixieError> {
+        Ok(self
+            .iter()?
+            .filter(move |res| res.as_ref().map_or(true, |(sym, _)| sym.typ == typ)))
+    }
+
+    /// Returns the version of the nth symbol, or `None` if it isn't
+    /// versioned
+    pub fn version(&self, n: usize) -> Result<Option<SymVersion<'a>>, PixieError> {
+        let versym = match self.versym {
+            Some(versym) => versym,
+            None => return Ok(None),
+        };
+        let versym = versym.as_slice()?.get(n * 2..).ok_or(PixieError::MalformedVersions)?;
+        let (_, versym) = Versym::from_bytes((versym, 0))?;
+        if versym.index() == Versym::LOCAL || versym.index() == Versym::GLOBAL {
+            return Ok(None);
+        }
+
+        let (name, defined) = match self.defined_version(versym.index())? {
+            Some(name) => (name, true),
+            None => (self.needed_version(versym.index())?, false),
+        };
+        Ok(Some(SymVersion {
+            name,
+            defined,
+            hidden: versym.is_hidden(),
+        }))
+    }
+
+    /// Finds the name of a version the object defines, by index
+    fn defined_version(&self, index: u16) -> Result<Option<&'a str>, PixieError> {
+        let mut entry = match self.verdef {
+            Some(verdef) => verdef.as_slice()?,
+            None => return Ok(None),
+        };
         loop {
-            let (sym, sym_name) = self.nth(i)?;
-            if sym_name == name {
-                return Ok(sym);
+            let (_, verdef) = Verdef::from_bytes((entry, 0))?;
+            if verdef.ndx == index {
+                let aux = entry.get(verdef.aux as usize..).ok_or(PixieError::MalformedVersions)?;
+                let (_, verdaux) = Verdaux::from_bytes((aux, 0))?;
+                return Ok(Some(self.string(verdaux.name)?));
+            }
+            if verdef.next == 0 {
+                return Ok(None);
             }
-            i += 1;
+            entry = entry.get(verdef.next as usize..).ok_or(PixieError::MalformedVersions)?;
         }
     }
+
+    /// Finds the name of a version the object needs, by index
+    fn needed_version(&self, index: u16) -> Result<&'a str, PixieError> {
+        let mut entry = self.verneed.ok_or(PixieError::MalformedVersions)?.as_slice()?;
+        loop {
+            let (_, verneed) = Verneed::from_bytes((entry, 0))?;
+            let mut aux = entry.get(verneed.aux as usize..).ok_or(PixieError::MalformedVersions)?;
+            for _ in 0..verneed.cnt {
+                let (_, vernaux) = Vernaux::from_bytes((aux, 0))?;
+                if vernaux.other == index {
+                    return self.string(vernaux.name);
+                }
+                aux = aux.get(vernaux.next as usize..).ok_or(PixieError::MalformedVersions)?;
+            }
+            if verneed.next == 0 {
+                return Err(PixieError::MalformedVersions);
+            }
+            entry = entry.get(verneed.next as usize..).ok_or(PixieError::MalformedVersions)?;
+        }
+    }
+
+    /// Returns how many symbols there are, counting the 0th one
+    pub fn len(&self) -> Result<usize, PixieError> {
+        match &self.index {
+            SymIndex::Gnu { table, chains } => {
+                // The symbol with the highest index is in the chain that
+                // starts last: walk it to its end.
+                let last = match table.buckets.iter().copied().max() {
+                    Some(start) if start >= table.symoffset => start,
+                    _ => return Ok(table.symoffset as usize),
+                };
+                let mut i = last;
+                while gnu_chain(chains, i - table.symoffset)? & 1 == 0 {
+                    i += 1;
+                }
+                Ok(i as usize + 1)
+            }
+            SymIndex::Sysv(table) => Ok(table.nchain as usize),
+            SymIndex::None => self
+                .count
+                .ok_or(PixieError::DynamicEntryNotFound(DynamicTagType::GnuHash)),
+        }
+    }
+
+    /// Returns true if there are no symbols at all, not even the 0th one
+    pub fn is_empty(&self) -> Result<bool, PixieError> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Find a defined symbol by name, using the symbol table's hash table.
+    /// `name` may ask for a version, as in `memcpy@GLIBC_2.14`: otherwise,
+    /// hidden versions are skipped.
+    pub fn by_name(&self, name: &str) -> Result<Sym, PixieError> {
+        let not_found = || PixieError::SymbolNotFound(name.into());
+        let (name, wanted) = match name.split_once('@') {
+            // `@@` marks the default version, we don't need to know that
+            Some((name, version)) => (name, Some(version.trim_start_matches('@'))),
+            None => (name, None),
+        };
+        let check = |i: u32| -> Result<Option<Sym>, PixieError> {
+            let (sym, sym_name) = self.nth(i as _)?;
+            if sym_name != name || sym.is_defined() == false {
+                return Ok(None);
+            }
+            let found = match (wanted, self.version(i as _)?) {
+                (Some(wanted), Some(version)) => version.name == wanted,
+                (Some(_), None) => false,
+                (None, Some(version)) => version.hidden == false,
+                (None, None) => true,
+            };
+            Ok(found.then(|| sym))
+        };
+
+        match &self.index {
+            SymIndex::Gnu { table, chains } => {
+                let h = gnu_hash(name.as_bytes());
+                if table.buckets.is_empty() || table.may_contain(h) == false {
+                    return Err(not_found());
+                }
+                let mut i = table.buckets[h as usize % table.buckets.len()];
+                if i < table.symoffset {
+                    return Err(not_found());
+                }
+                loop {
+                    let chain = gnu_chain(chains, i - table.symoffset)?;
+                    if chain | 1 == h | 1 {
+                        if let Some(sym) = check(i)? {
+                            return Ok(sym);
+                        }
+                    }
+                    if chain & 1 != 0 {
+                        return Err(not_found());
+                    }
+                    i += 1;
+                }
+            }
+            SymIndex::Sysv(table) => {
+                if table.buckets.is_empty() {
+                    return Err(not_found());
+                }
+                let h = sysv_hash(name.as_bytes());
+                let mut i = table.buckets[h as usize % table.buckets.len()];
+                // a chain can't be longer than the table, unless it loops
+                for _ in 0..table.chains.len() {
+                    if i == 0 {
+                        break;
+                    }
+                    if let Some(sym) = check(i)? {
+                        return Ok(sym);
+                    }
+                    i = *table.chains.get(i as usize).ok_or(PixieError::MalformedHashTable)?;
+                }
+                Err(not_found())
+            }
+            SymIndex::None => Err(PixieError::DynamicEntryNotFound(DynamicTagType::GnuHash)),
+        }
+    }
+}
+
+/// Reads the `n`th entry of a `DT_GNU_HASH` table's chains
+fn gnu_chain(chains: &[u8], n: u32) -> Result<u32, PixieError> {
+    let start = n as usize * 4;
+    chains
+        .get(start..start + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or(PixieError::MalformedHashTable)
 }
 
+/// What applying a relocation takes, see `MappedObject::resolve_rela`
+enum Fixup {
+    /// Writing a 64-bit value
+    Value(u64),
+    /// Calling the ifunc resolver at this address, and writing its result
+    Resolver(u64),
+    /// Copying these bytes
+    Copy(Vec<u8>),
+}
+
+/// Size of the AArch64 thread control block, which thread-local storage
+/// follows (aligned)
+const AARCH64_TCB_SIZE: u64 = 16;
+
+/// Size of the x86 thread control block left for the object, which
+/// thread-local storage precedes: glibc keeps the stack protector's canary
+/// at offset 0x28 (0x14 on i386), which ifunc resolvers may read.
+const X86_TCB_SIZE: u64 = 0x40;
+
+/// What AArch64 ifunc resolvers get a pointer to, glibc's `__ifunc_arg_t`
+#[repr(C)]
+struct IfuncArg {
+    /// Size of the struct, so fields can be added
+    size: u64,
+    hwcap: u64,
+    hwcap2: u64,
+}
+
+/// Set in the first argument of AArch64 ifunc resolvers, to say the second
+/// one points to an `IfuncArg`
+const IFUNC_ARG_HWCAP: u64 = 1 << 62;
+
 pub struct MappedObject<'a> {
     object: &'a Object<'a>,
 
@@ -334,12 +1048,36 @@ pub struct MappedObject<'a> {
 
     /// Allocated memory for the object
     mem: &'a mut [u8],
+
+    /// `AT_HWCAP` and `AT_HWCAP2`, for ifunc resolvers, see `set_hwcaps`
+    hwcaps: (u64, u64),
+
+    /// Thread pointer set up by `init_tls`, if it was
+    thread_pointer: Option<u64>,
 }
 
 impl<'a> MappedObject<'a> {
     /// If `at` is Some, map at a specific address. This only works
     /// with relocatable objects.
-    pub fn new(object: &'a Object, mut at: Option<u64>) -> Result<Self, PixieError> {
+    pub fn new(object: &'a Object, at: Option<u64>) -> Result<Self, PixieError> {
+        Self::map_with(object, at, |_, seg, dst| {
+            dst.copy_from_slice(seg.slice());
+            Ok(None)
+        })
+    }
+
+    /// Like `new`, but `fill` writes the file contents of the `i`th load
+    /// segment to `dst` instead, for objects whose slice doesn't hold them
+    /// (see `Object::from_headers`). Alternatively, `fill` can return a file
+    /// to map them from, see `map_from_file`.
+    pub fn map_with<F>(
+        object: &'a Object,
+        mut at: Option<u64>,
+        fill: F,
+    ) -> Result<Self, PixieError>
+    where
+        F: FnMut(usize, &Segment, &mut [u8]) -> Result<Option<FileOpts>, PixieError>,
+    {
         let hull = object.segments().load_convex_hull()?;
         let is_relocatable = hull.start == 0;
 
@@ -354,7 +1092,7 @@ impl<'a> MappedObject<'a> {
         let mem_len = (hull.end - hull.start) as u64;
 
         let mut map_opts = MmapOptions::new(mem_len);
-        // todo: adjust protections after copying segments
+        // writable for now, see `protect`
         map_opts.prot(MmapProt::READ | MmapProt::WRITE | MmapProt::EXEC);
         if let Some(at) = at {
             map_opts.at(at);
@@ -371,74 +1109,408 @@ impl<'a> MappedObject<'a> {
             hull,
             base_offset,
             mem,
+            hwcaps: (0, 0),
+            thread_pointer: None,
         };
-        mapped.copy_load_segments();
+        mapped.fill_load_segments(fill)?;
         Ok(mapped)
     }
 
+    /// Hands the hardware capabilities the kernel put in `env` (`AT_HWCAP`
+    /// and `AT_HWCAP2`) to the object's ifunc resolvers, for those that take
+    /// them as arguments, like AArch64 ones. Otherwise, they're told there
+    /// aren't any.
+    pub fn set_hwcaps(&mut self, env: &Env) {
+        self.hwcaps = (
+            env.vector(AuxvType::HWCAP).unwrap_or_default() as u64,
+            env.vector(AuxvType::HWCAP2).unwrap_or_default() as u64,
+        );
+    }
+
     /// Apply relocations with the given base offset
     pub fn relocate(&mut self, base_offset: u64) -> Result<(), PixieError> {
+        self.relocate_with(base_offset, &[])
+    }
+
+    /// Apply relocations with the given base offset. `COPY` relocations copy
+    /// their symbol's initial value from the first object in `scope` that
+    /// defines it, so those should already be relocated.
+    ///
+    /// `IRELATIVE` resolvers are called last, so they see everything else
+    /// relocated, along with thread-local storage, which is set up for them
+    /// (see `init_tls`). They run where the object is mapped, so objects that
+    /// have any can only be relocated for that (`base_offset()`): their
+    /// resolvers would follow pointers into memory that isn't mapped
+    /// otherwise, and pick implementations for the wrong CPU.
+    /// Thread-local relocations assume the object is the only module with
+    /// thread-local storage, laid out by `init_tls`.
+    pub fn relocate_with(
+        &mut self,
+        base_offset: u64,
+        scope: &[&MappedObject],
+    ) -> Result<(), PixieError> {
         if self.is_relocatable() == false {
             return Err(PixieError::CannotRelocateNonRelocatableObject);
         }
 
-        let dyn_entries = self.object.read_dynamic_entries()?;
-        let syms = dyn_entries.syms()?;
-
-        let relas = dyn_entries
-            .find(DynamicTagType::Rela)?
-            .parse_all(dyn_entries.find(DynamicTagType::RelaSz)?);
-        let plt_relas: Box<dyn Iterator<Item = _>> = match dyn_entries.find(DynamicTagType::JmpRel)
-        {
-            Ok(jmprel) => Box::new(jmprel.parse_all(dyn_entries.find(DynamicTagType::PltRelSz)?)),
-            Err(_) => Box::new(core::iter::empty()) as _,
+        // Work out every fixup first: they're looked up in memory we're
+        // about to write to.
+        let fixups = {
+            let dyn_entries = self.read_dynamic_entries()?;
+            // static-pie executables may not have any symbols
+            let syms = dyn_entries.syms().ok();
+
+            let relas: Box<dyn Iterator<Item = _>> =
+                match dyn_entries.find(DynamicTagType::Rela) {
+                    Ok(rela) => {
+                        Box::new(rela.parse_all(dyn_entries.find(DynamicTagType::RelaSz)?)?)
+                    }
+                    Err(_) => Box::new(core::iter::empty()) as _,
+                };
+            // i386 objects use relocations without explicit addends
+            let rels: Box<dyn Iterator<Item = _>> =
+                match dyn_entries.find(DynamicTagType::Rel) {
+                    Ok(rel) => Box::new(self.rels_with_addends(
+                        rel.parse_all(dyn_entries.find(DynamicTagType::RelSz)?)?,
+                    )),
+                    Err(_) => Box::new(core::iter::empty()) as _,
+                };
+            let plt_rels_are_rela = dyn_entries
+                .find(DynamicTagType::PltRel)
+                .map_or(true, |pltrel| pltrel.as_u64() != DynamicTagType::Rel.id());
+            let plt_relas: Box<dyn Iterator<Item = _>> =
+                match dyn_entries.find(DynamicTagType::JmpRel) {
+                    Ok(jmprel) => {
+                        let len = dyn_entries.find(DynamicTagType::PltRelSz)?;
+                        if plt_rels_are_rela {
+                            Box::new(jmprel.parse_all(len)?)
+                        } else {
+                            Box::new(self.rels_with_addends(jmprel.parse_all(len)?))
+                        }
+                    }
+                    Err(_) => Box::new(core::iter::empty()) as _,
+                };
+
+            relas
+                .chain(rels)
+                .chain(plt_relas)
+                .map(|rela| self.resolve_rela(syms.as_ref(), &rela?, base_offset, scope))
+                .collect::<Result<Vec<_>, PixieError>>()?
         };
 
-        for rela in relas.chain(plt_relas) {
-            let rela = rela?;
-            self.apply_rela(&syms, &rela, base_offset)?;
+        // ifunc resolvers may need everything else to be relocated already
+        let (resolvers, fixups): (Vec<_>, Vec<_>) = fixups
+            .into_iter()
+            .partition(|(_, fixup)| matches!(fixup, Fixup::Resolver(_)));
+        for (mem_offset, fixup) in fixups {
+            self.apply_rela(mem_offset, fixup);
+        }
+        // resolvers may use thread-locals, copied from the relocated image
+        if resolvers.is_empty() == false {
+            self.init_tls()?;
+        }
+        for (mem_offset, fixup) in resolvers {
+            self.apply_rela(mem_offset, fixup);
         }
         Ok(())
     }
 
-    /// Apply a single relocation
-    fn apply_rela(&mut self, syms: &Syms, rela: &Rela, base_offset: u64) -> Result<(), PixieError> {
-        match rela.typ {
+    /// Reads all dynamic entries from memory, rather than from the file like
+    /// `Object::read_dynamic_entries` does, so that it works for objects
+    /// mapped with `map_with`. Only valid for relocatable objects, whose
+    /// addresses are offsets into `mem`.
+    fn read_dynamic_entries(&self) -> Result<DynamicEntries<'_>, PixieError> {
+        let dyn_seg = self.object.segments().find(SegmentType::Dynamic)?;
+        let dynamic = self.vaddr_slice(dyn_seg.header().mem_range())?;
+        DynamicEntries::parse(dynamic, &self.mem[..], self.object.header().class)
+    }
+
+    /// Turns `Rel` relocations into `Rela` ones, reading their addends from
+    /// where they apply. Those whose value doesn't depend on an addend get
+    /// zero instead: what's there may be anything (like the address of a
+    /// lazy binding stub, for `JUMP_SLOT`).
+    fn rels_with_addends<'b>(
+        &'b self,
+        rels: impl Iterator<Item = Result<Rel, PixieError>> + 'b,
+    ) -> impl Iterator<Item = Result<Rela, PixieError>> + 'b {
+        rels.map(move |rel| {
+            let rel = rel?;
+            let addend = match rel.typ {
+                RelType::GlobDat | RelType::JumpSlot | RelType::Copy | RelType::DtpMod64 => 0,
+                _ => self.read_word(self.vaddr_to_mem_offset(rel.offset)),
+            };
+            Ok(rel.with_addend(addend))
+        })
+    }
+
+    /// Works out a single relocation: returns the memory offset it applies
+    /// to, and what to do there
+    fn resolve_rela(
+        &self,
+        syms: Option<&Syms>,
+        rela: &Rela,
+        base_offset: u64,
+        scope: &[&MappedObject],
+    ) -> Result<(usize, Fixup), PixieError> {
+        // some relocations don't use symbols, their symbol index is zero,
+        // and the 0th symbol is all zeroes.
+        let sym = match rela.sym {
+            0 => None,
+            n => {
+                let syms = syms.ok_or(PixieError::DynamicEntryNotFound(DynamicTagType::SymTab))?;
+                Some(syms.nth(n as _)?)
+            }
+        };
+        let sym_value = sym.as_ref().map_or(0, |(sym, _)| sym.value);
+
+        let typ = match self.object.header().machine {
+            ElfMachine::AArch64 => rela.typ.from_aarch64(),
+            _ => rela.typ,
+        };
+        let fixup = match typ {
             RelType::_64 | RelType::GlobDat | RelType::JumpSlot | RelType::Relative => {
-                // we support these
+                Fixup::Value(base_offset + sym_value + rela.addend)
+            }
+            RelType::IRelative => {
+                if base_offset != self.base_offset() {
+                    return Err(PixieError::IRelativeElsewhere);
+                }
+                Fixup::Resolver(self.vaddr_to_addr(rela.addend))
+            }
+            // we're the only module
+            RelType::DtpMod64 => Fixup::Value(1),
+            RelType::DtpOff64 => Fixup::Value(sym_value.wrapping_add(rela.addend)),
+            RelType::TpOff64 => {
+                let offset = self.tls_offset()?;
+                Fixup::Value(sym_value.wrapping_add(rela.addend).wrapping_add(offset as u64))
+            }
+            RelType::Copy => {
+                let (sym, name) = sym.ok_or_else(|| PixieError::UnsupportedRela(rela.clone()))?;
+                let (obj, source) = scope
+                    .iter()
+                    .find_map(|obj| Some((obj, obj.lookup_sym(name).ok()?)))
+                    .ok_or_else(|| PixieError::CopySourceNotFound(name.into()))?;
+                let bytes = obj.vaddr_slice(source.value..source.value.saturating_add(sym.size))?;
+                Fixup::Copy(bytes.to_vec())
             }
             _ => {
                 return Err(PixieError::UnsupportedRela(rela.clone()));
             }
-        }
+        };
+
+        // make sure `apply_rela` has room to write
+        let len = match &fixup {
+            Fixup::Copy(bytes) => bytes.len() as u64,
+            _ => self.object.header().class.word_size() as u64,
+        };
+        let mem_range = self.mem_range(rela.offset..rela.offset.saturating_add(len))?;
+        Ok((mem_range.start, fixup))
+    }
+
+    /// Applies a single relocation, as worked out by `resolve_rela`
+    fn apply_rela(&mut self, mem_offset: usize, fixup: Fixup) {
+        let value = match fixup {
+            Fixup::Value(value) => value,
+            Fixup::Resolver(addr) => match self.object.header().machine {
+                ElfMachine::AArch64 => {
+                    let (hwcap, hwcap2) = self.hwcaps;
+                    let arg = IfuncArg {
+                        size: core::mem::size_of::<IfuncArg>() as u64,
+                        hwcap,
+                        hwcap2,
+                    };
+                    let resolver: extern "C" fn(u64, *const IfuncArg) -> usize =
+                        unsafe { core::mem::transmute(addr as usize) };
+                    resolver(hwcap | IFUNC_ARG_HWCAP, &arg) as u64
+                }
+                _ => {
+                    let resolver: extern "C" fn() -> usize =
+                        unsafe { core::mem::transmute(addr as usize) };
+                    resolver() as u64
+                }
+            },
+            Fixup::Copy(bytes) => {
+                self.mem[mem_offset..][..bytes.len()].copy_from_slice(&bytes);
+                return;
+            }
+        };
+        let word_size = self.object.header().class.word_size();
+        self.mem[mem_offset..][..word_size].copy_from_slice(&value.to_le_bytes()[..word_size]);
+    }
 
-        // some relocations don't use symbols, we'll just use the 0th symbol
-        // for them, which is fine.
-        let (sym, _) = syms.nth(rela.sym as _)?;
-        let value = base_offset + sym.value + rela.addend;
+    /// Reads an address-sized value from memory
+    fn read_word(&self, mem_offset: usize) -> u64 {
+        let word_size = self.object.header().class.word_size();
+        let mut bytes = [0u8; 8];
+        bytes[..word_size].copy_from_slice(&self.mem[mem_offset..][..word_size]);
+        u64::from_le_bytes(bytes)
+    }
 
-        let mem_offset = self.vaddr_to_mem_offset(rela.offset);
+    /// Returns where the object's thread-local storage starts, relative to
+    /// the thread pointer, see `init_tls`
+    fn tls_offset(&self) -> Result<i64, PixieError> {
+        let tls = self.object.segments().find(SegmentType::Tls)?.header();
+        let align = core::cmp::max(tls.align, 1);
+        Ok(match self.object.header().machine {
+            // right after the thread control block
+            ElfMachine::AArch64 => ((AARCH64_TCB_SIZE + align - 1) / align * align) as i64,
+            // right below the thread pointer
+            _ => -(((tls.mem_size + align - 1) / align * align) as i64),
+        })
+    }
+
+    /// Sets up thread-local storage for the current thread: allocates it
+    /// along with a minimal thread control block, copies its initial
+    /// contents, and points the thread pointer at it. Objects without any
+    /// still get the thread control block. Returns the new thread pointer.
+    /// It's only set up once, later calls return the same one.
+    ///
+    /// On x86, the storage comes first and the thread pointer points past it,
+    /// to the thread control block. On AArch64, the thread pointer points to
+    /// the thread control block, followed by the storage.
+    pub fn init_tls(&mut self) -> Result<u64, PixieError> {
+        if let Some(tp) = self.thread_pointer {
+            return Ok(tp);
+        }
+        let is_aarch64 = self.object.header().machine == ElfMachine::AArch64;
+        let (mem_size, image, offset) = match self.object.segments().find(SegmentType::Tls) {
+            Ok(tls) => {
+                let tls = tls.header();
+                let range = tls.vaddr..tls.vaddr.saturating_add(tls.file_size);
+                (tls.mem_size, self.vaddr_slice(range)?, self.tls_offset()?)
+            }
+            // no storage, just the thread control block
+            Err(_) => (0, &[][..], if is_aarch64 { AARCH64_TCB_SIZE as i64 } else { 0 }),
+        };
+
+        let (len, tp_offset, image_offset) = if is_aarch64 {
+            // the thread control block is left zeroed
+            (offset as u64 + mem_size, 0, offset as u64)
+        } else {
+            // the thread control block only holds a pointer to itself
+            let below = offset.unsigned_abs();
+            (below + X86_TCB_SIZE, below, 0)
+        };
+        let block = MmapOptions::new(len).map()?;
+        let tp = block + tp_offset;
         unsafe {
-            let target = self.mem.as_ptr().add(mem_offset) as *mut u64;
-            *target = value;
+            let image_start = (block + image_offset) as usize as *mut u8;
+            core::ptr::copy_nonoverlapping(image.as_ptr(), image_start, image.len());
+            if is_aarch64 == false {
+                *(tp as usize as *mut usize) = tp as usize;
+            }
+        }
+        encore::thread::set_thread_pointer(tp)?;
+        self.thread_pointer = Some(tp);
+        Ok(tp)
+    }
+
+    /// Gives each load segment the protection its flags ask for, and makes
+    /// the pages between them inaccessible. Until this is called, the whole
+    /// mapping is readable, writable and executable, so call it once done
+    /// writing to it (filling segments, relocating...).
+    pub fn protect(&self) -> Result<(), PixieError> {
+        let mem_start = self.mem.as_ptr() as u64;
+        let mem_end = ceil(mem_start + self.mem.len() as u64);
+        memmap::protect(mem_start, mem_end - mem_start, MmapProt::empty())?;
+
+        let loads = self
+            .object
+            .segments()
+            .of_type(SegmentType::Load)
+            .map(|seg| (self.vaddr_pages(seg.header().mem_range()), seg.header().prot()))
+            .filter(|(pages, _)| pages.is_empty() == false)
+            .collect::<Vec<_>>();
+        for (pages, prot) in &loads {
+            memmap::protect(pages.start, pages.end - pages.start, *prot)?;
         }
 
+        // Segments that don't start or end on a page boundary may share
+        // that page with a neighbour: it needs the flags of both.
+        for (pages, _) in &loads {
+            for page in [pages.start, pages.end - 0x1000] {
+                let prot = loads
+                    .iter()
+                    .filter(|(other, _)| other.contains(&page))
+                    .fold(MmapProt::empty(), |acc, (_, prot)| acc | *prot);
+                memmap::protect(page, 0x1000, prot)?;
+            }
+        }
         Ok(())
     }
 
+    /// Makes the `GNU_RELRO` segment, if any, read-only. Only call this
+    /// after `relocate`: for objects relocated by someone else (the dynamic
+    /// loader, or a static-pie itself), leave it to them.
+    pub fn protect_relro(&self) -> Result<(), PixieError> {
+        let relro = match self.object.segments().find(SegmentType::GnuRelro) {
+            Ok(relro) => relro,
+            Err(_) => return Ok(()),
+        };
+
+        // like the dynamic loader, don't protect a partial last page
+        let range = relro.header().mem_range();
+        let start = floor(self.vaddr_to_addr(range.start));
+        let end = floor(self.vaddr_to_addr(range.end));
+        if end > start {
+            memmap::protect(start, end - start, MmapProt::READ)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the pages spanned by a range of (non-relocated) vaddrs, as
+    /// actual addresses
+    fn vaddr_pages(&self, range: Range<u64>) -> Range<u64> {
+        floor(self.vaddr_to_addr(range.start))..ceil(self.vaddr_to_addr(range.end))
+    }
+
     /// Returns the (non-relocated) vaddr of a symbol by name
     pub fn lookup_sym(&self, name: &str) -> Result<Sym, PixieError> {
         let dyn_entries = self.object.read_dynamic_entries()?;
         dyn_entries.syms()?.by_name(name)
     }
 
-    /// Copies load segments from the file into the memory we mapped
-    fn copy_load_segments(&mut self) {
-        for seg in self.object.segments().of_type(SegmentType::Load) {
+    /// Fills the memory we mapped with the file contents of load segments
+    fn fill_load_segments<F>(&mut self, mut fill: F) -> Result<(), PixieError>
+    where
+        F: FnMut(usize, &Segment, &mut [u8]) -> Result<Option<FileOpts>, PixieError>,
+    {
+        let object = self.object;
+        for (i, seg) in object.segments().of_type(SegmentType::Load).enumerate() {
             let mem_start = self.vaddr_to_mem_offset(seg.header().vaddr);
-            let dst = &mut self.mem[mem_start..][..seg.slice().len()];
-            dst.copy_from_slice(seg.slice());
+            let dst = &mut self.mem[mem_start..][..seg.header().file_size as usize];
+            if let Some(file) = fill(i, seg, dst)? {
+                self.map_from_file(seg, file)?;
+            }
         }
+        Ok(())
+    }
+
+    /// Maps the file contents of `seg` from `file`, over the memory we
+    /// mapped, so that its pages are shared with whoever else maps them.
+    /// `file.offset` is where the contents start: it has to match the
+    /// segment's address within a page, and the rest of the pages they span
+    /// in the file should be zeroes, as they're mapped too.
+    fn map_from_file(&mut self, seg: &Segment, file: FileOpts) -> Result<(), PixieError> {
+        let header = seg.header();
+        if header.file_size == 0 {
+            return Ok(());
+        }
+        if file.offset & 0xFFF != header.vaddr & 0xFFF {
+            return Err(EncoreError::MmapFileUnaligned(file.offset).into());
+        }
+
+        let start = floor(self.vaddr_to_addr(header.vaddr));
+        let end = ceil(self.vaddr_to_addr(header.vaddr + header.file_size));
+        MmapOptions::new(end - start)
+            .prot(MmapProt::READ | MmapProt::WRITE | MmapProt::EXEC)
+            .file(FileOpts {
+                fd: file.fd,
+                offset: floor(file.offset),
+            })
+            .at(start)
+            .map()?;
+        Ok(())
     }
 
     /// Convert a vaddr to a memory offset
@@ -446,9 +1518,26 @@ impl<'a> MappedObject<'a> {
         (vaddr - self.hull.start) as _
     }
 
+    /// Convert a vaddr to the address it's actually mapped at
+    pub fn vaddr_to_addr(&self, vaddr: u64) -> u64 {
+        self.base() + self.vaddr_to_mem_offset(vaddr) as u64
+    }
+
+    /// Converts a range of vaddrs to a range of memory offsets, checking that
+    /// it lies within the mapped object
+    fn mem_range(&self, range: Range<u64>) -> Result<Range<usize>, PixieError> {
+        let out_of_range = || PixieError::AddressOutOfRange(range.start, range.end);
+        let start = range.start.checked_sub(self.hull.start).ok_or_else(out_of_range)?;
+        let end = range.end.checked_sub(self.hull.start).ok_or_else(out_of_range)?;
+        if start > end || end > self.mem.len() as u64 {
+            return Err(out_of_range());
+        }
+        Ok(start as usize..end as usize)
+    }
+
     /// Returns a view of (potentially relocated) `mem` for a given range
-    pub fn vaddr_slice(&self, range: Range<u64>) -> &[u8] {
-        &self.mem[self.vaddr_to_mem_offset(range.start)..self.vaddr_to_mem_offset(range.end)]
+    pub fn vaddr_slice(&self, range: Range<u64>) -> Result<&[u8], PixieError> {
+        Ok(&self.mem[self.mem_range(range)?])
     }
 
     /// Returns true if the object's load convex hull start is zero,
@@ -1,10 +1,28 @@
-use crate::PixieError;
+use crate::{
+    crc32,
+    Codec,
+    Encryption,
+    Guest,
+    PixieError,
+    SIGNATURE_LEN,
+};
 use alloc::{
     format,
+    string::String,
     vec::Vec,
 };
 use core::ops::Range;
-use deku::prelude::*;
+use deku::{
+    bitvec::{
+        BitSlice,
+        BitView,
+        Msb0,
+    },
+    ctx::Limit,
+    error::NeedSize,
+    prelude::*,
+};
+use encore::env::Env;
 
 #[derive(Debug, DekuRead, DekuWrite)]
 #[deku(magic = b"pixiendm")]
@@ -13,7 +31,12 @@ pub struct EndMarker {
     pub manifest_offset: usize,
 }
 
-#[derive(Debug, DekuRead, DekuWrite)]
+impl EndMarker {
+    /// Size of the end marker, magic included, in bytes
+    pub const SIZE: usize = 16;
+}
+
+#[derive(Debug, Clone, DekuRead, DekuWrite)]
 pub struct Resource {
     #[deku(bytes = 8)]
     pub offset: usize,
@@ -25,20 +48,654 @@ impl Resource {
     pub fn as_range(&self) -> Range<usize> {
         self.offset..self.offset + self.len
     }
+
+    /// Returns the resource's bytes in `full_slice`, the whole file. `what`
+    /// names the resource if it's out of range.
+    pub fn slice<'a>(
+        &self,
+        what: &'static str,
+        full_slice: &'a [u8],
+    ) -> Result<&'a [u8], PixieError> {
+        self.check_bounds(what, full_slice.len())?;
+        Ok(&full_slice[self.as_range()])
+    }
+
+    /// Checks that the resource lies within the first `file_len` bytes of
+    /// the file, so that `as_range` can be used to index it.
+    pub fn check_bounds(&self, what: &'static str, file_len: usize) -> Result<(), PixieError> {
+        match self.offset.checked_add(self.len) {
+            Some(end) if end <= file_len => Ok(()),
+            _ => Err(PixieError::ResourceOutOfRange {
+                what,
+                offset: self.offset,
+                len: self.len,
+                file_len,
+            }),
+        }
+    }
+}
+
+/// A resource that was compressed (and possibly encrypted) before being
+/// written out
+#[derive(Debug, Clone, DekuRead, DekuWrite)]
+pub struct CompressedResource {
+    pub codec: Codec,
+    pub resource: Resource,
+    /// CRC-32 of the bytes as stored in the file, i.e. compressed, then
+    /// encrypted if the manifest says so
+    pub compressed_crc32: u32,
+    /// CRC-32 of the bytes `codec` decompresses to, or 0 if they're
+    /// encrypted: the AEAD tag already vouches for them, and a checksum in
+    /// the clear would tell something about them.
+    pub uncompressed_crc32: u32,
+}
+
+impl CompressedResource {
+    /// Describes `stored`, written at `offset`, which was produced by
+    /// compressing `uncompressed` with `codec`, then encrypting it if
+    /// `uncompressed` is `None`.
+    pub fn new(codec: Codec, offset: usize, stored: &[u8], uncompressed: Option<&[u8]>) -> Self {
+        Self {
+            codec,
+            resource: Resource {
+                offset,
+                len: stored.len(),
+            },
+            compressed_crc32: crc32(stored),
+            uncompressed_crc32: uncompressed.map(crc32).unwrap_or_default(),
+        }
+    }
+
+    /// Returns the range of the stored bytes
+    pub fn as_range(&self) -> Range<usize> {
+        self.resource.as_range()
+    }
+
+    /// Returns the bytes stored in the full file, after checking them
+    /// against their checksum.
+    pub fn stored<'a>(&self, full_slice: &'a [u8]) -> Result<&'a [u8], PixieError> {
+        let stored = self.resource.slice("resource", full_slice)?;
+        check_crc32("compressed", stored, self.compressed_crc32)?;
+        Ok(stored)
+    }
+
+    /// Decompresses `compressed` (the stored bytes, decrypted if needed),
+    /// checking the result against its checksum.
+    pub fn decompress(&self, compressed: &[u8]) -> Result<Vec<u8>, PixieError> {
+        let uncompressed = self.codec.decompress(compressed)?;
+        check_crc32("uncompressed", &uncompressed, self.uncompressed_crc32)?;
+        Ok(uncompressed)
+    }
+
+    /// Returns the original bytes, checked, decrypted and decompressed. If
+    /// they're encrypted, `env` is used to find the key.
+    pub fn load(
+        &self,
+        full_slice: &[u8],
+        encryption: &Encryption,
+        env: &Env,
+    ) -> Result<Vec<u8>, PixieError> {
+        let stored = self.stored(full_slice)?;
+        match encryption {
+            Encryption::None => self.decompress(stored),
+            encryption => Ok(self.codec.decompress(&encryption.decrypt(stored, env)?)?),
+        }
+    }
+}
+
+/// An extra file bundled with the guests (see `minipak --embed`)
+#[derive(Debug, Clone, DekuRead, DekuWrite)]
+pub struct NamedResource {
+    /// The name the guest knows the file by
+    pub name: ManifestString,
+    /// How the file is encrypted at rest, if at all
+    pub encryption: Encryption,
+    pub resource: CompressedResource,
+}
+
+impl NamedResource {
+    /// Returns the original bytes, checked, decrypted and decompressed. If
+    /// they're encrypted, `env` is used to find the key.
+    pub fn load(&self, full_slice: &[u8], env: &Env) -> Result<Vec<u8>, PixieError> {
+        self.resource.load(full_slice, &self.encryption, env)
+    }
+}
+
+/// Environment variable that picks which guest to run, see
+/// `Manifest::select_guest`
+pub const GUEST_VAR: &str = "MINIPAK_GUEST";
+
+/// Returns the name a guest is selected by when it runs as `path`, and the
+/// name minipak gives a guest packed from `path`: its file name.
+pub fn guest_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Returns the name of the environment variable that tells the guest where
+/// to find the embedded file `name`: `MINIPAK_EMBED_` followed by `name`,
+/// uppercased, with anything that isn't a letter or digit replaced by `_`.
+/// Before launching the guest, stage2 writes the file to a memory file and
+/// points this variable to it.
+pub fn embed_var_name(name: &str) -> String {
+    let suffix: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+    format!("MINIPAK_EMBED_{}", suffix)
+}
+
+/// A length-prefixed UTF-8 string
+#[derive(Debug, Clone, Default, DekuRead, DekuWrite)]
+pub struct ManifestString {
+    len: u16,
+    #[deku(reader = "read_bytes(deku::rest, *len as usize)")]
+    bytes: Vec<u8>,
+}
+
+impl ManifestString {
+    /// Fails if `s` is longer than `u16::MAX` bytes, as its length wouldn't
+    /// fit
+    pub fn new(s: &str) -> Result<Self, PixieError> {
+        Ok(Self {
+            len: s.len().try_into().map_err(|_| PixieError::StringTooLong(s.len()))?,
+            bytes: s.as_bytes().into(),
+        })
+    }
+
+    /// Returns the string, or an empty string if it isn't valid UTF-8
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes).unwrap_or_default()
+    }
+}
+
+/// Reads `len` bytes, after checking that there are that many left, so that
+/// a corrupted length fails to parse instead of making us allocate a huge
+/// buffer.
+fn read_bytes(
+    input: &BitSlice<u8, Msb0>,
+    len: usize,
+) -> Result<(&BitSlice<u8, Msb0>, Vec<u8>), DekuError> {
+    let bits = len.saturating_mul(8);
+    if bits > input.len() {
+        return Err(DekuError::Incomplete(NeedSize::new(bits)));
+    }
+    Vec::read(input, Limit::new_count(len))
 }
 
+pub(crate) fn check_crc32(
+    what: &'static str,
+    data: &[u8],
+    expected: u32,
+) -> Result<(), PixieError> {
+    let actual = crc32(data);
+    if actual != expected {
+        return Err(PixieError::ChecksumMismatch {
+            what,
+            expected,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Version of the manifest format.
+///
+/// Loaders refuse manifests with a major version they don't know. Within a
+/// major version, new record types may be added, and fields may be appended
+/// to existing records (bumping the minor version). Loaders skip the records
+/// and trailing fields they don't know, and read the fields a record has
+/// according to the minor version.
+#[derive(Debug, Clone, Copy, PartialEq, DekuRead, DekuWrite)]
+pub struct FormatVersion {
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl FormatVersion {
+    /// The version this build of pixie writes. It reads every earlier
+    /// version with the same major.
+    pub const CURRENT: Self = Self { major: 1, minor: 4 };
+}
+
+impl core::fmt::Display for FormatVersion {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Fixed-layout start of the manifest, followed by `record_count` records
 #[derive(Debug, DekuRead, DekuWrite)]
 #[deku(magic = b"piximani")]
+struct ManifestHeader {
+    version: FormatVersion,
+    record_count: u32,
+}
+
+/// Type of a manifest record
+#[derive(Debug, Clone, Copy, PartialEq, DekuRead, DekuWrite)]
+#[deku(type = "u16")]
+pub enum RecordType {
+    /// A `Resource`, the stage2 shared object
+    #[deku(id = "1")]
+    Stage2,
+    /// A `CompressedResource`, the whole guest executable, from when there
+    /// could only be one (1.0 and 1.1). Read, but no longer written.
+    #[deku(id = "2")]
+    LegacyGuest,
+    /// An `Encryption`, how the legacy guest is encrypted. Omitted if it
+    /// isn't. Read, but no longer written.
+    #[deku(id = "3")]
+    LegacyEncryption,
+    /// An Ed25519 signature. Omitted if the executable isn't signed, always
+    /// the last record otherwise.
+    #[deku(id = "4")]
+    Signature,
+    /// A `NamedResource`, one per file bundled with the guests. Since 1.1.
+    #[deku(id = "5")]
+    Embed,
+    /// A `Guest`, one per guest executable. The first one runs unless
+    /// another one is selected. Since 1.2, its fields depend on the minor
+    /// version.
+    #[deku(id = "6")]
+    Guest,
+    /// Written by a newer minipak, skipped
+    #[deku(id_pat = "_")]
+    Other(u16),
+}
+
+/// A type-length-value entry of the manifest
+#[derive(Debug, DekuRead, DekuWrite)]
+struct Record {
+    typ: RecordType,
+    len: u32,
+    #[deku(reader = "read_bytes(deku::rest, *len as usize)")]
+    data: Vec<u8>,
+}
+
+impl Record {
+    /// Size of a signature record, in bytes
+    const SIGNATURE_LEN: usize = 2 + 4 + SIGNATURE_LEN;
+
+    fn new(typ: RecordType, data: Vec<u8>) -> Self {
+        Self {
+            typ,
+            len: data.len() as _,
+            data,
+        }
+    }
+
+    /// Parses the record's value
+    fn parse<'a, T>(&'a self) -> Result<T, DekuError>
+    where
+        T: DekuContainerRead<'a>,
+    {
+        let (_, t) = T::from_bytes((&self.data, 0))?;
+        Ok(t)
+    }
+
+    /// Parses the record's value, for values whose layout depends on the
+    /// format version
+    fn parse_with<'a, T, Ctx>(&'a self, ctx: Ctx) -> Result<T, DekuError>
+    where
+        T: DekuRead<'a, Ctx>,
+    {
+        let (_, t) = T::read(self.data.view_bits(), ctx)?;
+        Ok(t)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Manifest {
     pub stage2: Resource,
-    pub guest: Resource,
+    /// Guest executables, there's always at least one
+    pub guests: Vec<Guest>,
+    /// Extra files bundled with the guests
+    pub embeds: Vec<NamedResource>,
+    /// Ed25519 signature over the manifest and all resources, if the
+    /// executable was signed. See `Manifest::sign`.
+    pub signature: Option<[u8; SIGNATURE_LEN]>,
 }
 
 impl Manifest {
-    pub fn read_from_full_slice(slice: &[u8]) -> Result<Self, PixieError> {
-        let (_, endmarker) = EndMarker::from_bytes((&slice[slice.len() - 16..], 0)).unwrap();
+    /// Serializes the manifest, in the current format version
+    pub fn to_bytes(&self) -> Result<Vec<u8>, PixieError> {
+        let mut records = alloc::vec![Record::new(RecordType::Stage2, self.stage2.to_bytes()?)];
+        for guest in &self.guests {
+            records.push(Record::new(RecordType::Guest, guest.to_bytes()?));
+        }
+        for embed in &self.embeds {
+            records.push(Record::new(RecordType::Embed, embed.to_bytes()?));
+        }
+        if let Some(signature) = &self.signature {
+            records.push(Record::new(RecordType::Signature, signature.to_vec()));
+        }
+
+        let header = ManifestHeader {
+            version: FormatVersion::CURRENT,
+            record_count: records.len() as _,
+        };
+        let mut bytes = header.to_bytes()?;
+        for record in &records {
+            bytes.extend(record.to_bytes()?);
+        }
+        Ok(bytes)
+    }
+
+    /// Parses a manifest at the start of `slice`. Also returns the length of
+    /// the part covered by the signature, that is, everything up to the
+    /// signature record. Records that run past the end of `slice` are
+    /// reported as `ManifestOutOfRange`, with offsets relative to `slice`.
+    pub(crate) fn parse(slice: &[u8]) -> Result<(Self, usize), PixieError> {
+        let (mut input, header) = ManifestHeader::from_bytes((slice, 0))?;
+        if header.version.major != FormatVersion::CURRENT.major {
+            return Err(PixieError::UnsupportedManifestVersion(header.version));
+        }
+
+        let mut stage2 = None;
+        let mut guests = Vec::new();
+        let mut legacy_guest = None;
+        let mut legacy_encryption = Encryption::None;
+        let mut embeds = Vec::new();
+        let mut signature = None;
+        let mut signed_len = None;
+
+        for _ in 0..header.record_count {
+            let record_start = slice.len() - input.0.len();
+            let out_of_range = |e: DekuError| match e {
+                DekuError::Incomplete(_) => PixieError::ManifestOutOfRange {
+                    offset: record_start,
+                    file_len: slice.len(),
+                },
+                e => PixieError::Deku(e),
+            };
+            let (rest, record) = Record::from_bytes(input).map_err(out_of_range)?;
+            input = rest;
 
-        let (_, manifest) = Manifest::from_bytes((&slice[endmarker.manifest_offset..], 0)).unwrap();
+            match record.typ {
+                RecordType::Stage2 => stage2 = Some(record.parse().map_err(out_of_range)?),
+                RecordType::Guest => {
+                    let guest = record.parse_with(header.version.minor);
+                    guests.push(guest.map_err(out_of_range)?);
+                }
+                RecordType::LegacyGuest => {
+                    legacy_guest = Some(record.parse().map_err(out_of_range)?);
+                }
+                RecordType::LegacyEncryption => {
+                    legacy_encryption = record.parse().map_err(out_of_range)?;
+                }
+                RecordType::Embed => embeds.push(record.parse().map_err(out_of_range)?),
+                RecordType::Signature => {
+                    let bytes: Result<[u8; SIGNATURE_LEN], _> = record.data.as_slice().try_into();
+                    signature = Some(bytes.map_err(|_| PixieError::InvalidSignatureRecord)?);
+                    signed_len = Some(record_start);
+                }
+                RecordType::Other(_) => {
+                    // written by a newer minipak, we can do without it
+                }
+            }
+        }
+
+        if let Some(resource) = legacy_guest {
+            guests.push(Guest::legacy(resource, legacy_encryption));
+        }
+        if guests.is_empty() {
+            return Err(PixieError::MissingManifestRecord(RecordType::Guest));
+        }
+        let manifest = Self {
+            stage2: stage2.ok_or(PixieError::MissingManifestRecord(RecordType::Stage2))?,
+            guests,
+            embeds,
+            signature,
+        };
+        let signed_len = signed_len.unwrap_or(slice.len() - input.0.len());
+        Ok((manifest, signed_len))
+    }
+
+    /// Serializes the manifest as it'll be signed: with a signature record,
+    /// but without its contents (nor anything after it).
+    pub(crate) fn signed_bytes(&self) -> Result<Vec<u8>, PixieError> {
+        let mut signed = self.clone();
+        signed.signature = Some([0; SIGNATURE_LEN]);
+        let mut bytes = signed.to_bytes()?;
+        bytes.truncate(bytes.len() - Record::SIGNATURE_LEN);
+        Ok(bytes)
+    }
+
+    /// Returns the guest called `name`, if any
+    pub fn find_guest(&self, name: &str) -> Option<&Guest> {
+        self.guests.iter().find(|guest| guest.name.as_str() == name)
+    }
+
+    /// Picks which guest to run: the one named by the `MINIPAK_GUEST`
+    /// environment variable if it's set, otherwise the one named like the
+    /// program (the basename of `argv[0]`), otherwise the first one.
+    pub fn select_guest(&self, env: &Env) -> Result<&Guest, PixieError> {
+        if let Some(name) = env.var(GUEST_VAR) {
+            return self
+                .find_guest(name)
+                .ok_or_else(|| PixieError::GuestNotFound(name.into()));
+        }
+
+        let program_name = env.args.first().map(|arg| guest_name(arg));
+        Ok(program_name
+            .and_then(|name| self.find_guest(name))
+            .unwrap_or(&self.guests[0]))
+    }
+
+    /// Returns the part of the full file that starts with the manifest and
+    /// ends right before the end marker.
+    pub(crate) fn locate(slice: &[u8]) -> Result<&[u8], PixieError> {
+        let marker_start = slice
+            .len()
+            .checked_sub(EndMarker::SIZE)
+            .ok_or(PixieError::MissingEndMarker)?;
+        let (_, endmarker) = EndMarker::from_bytes((&slice[marker_start..], 0))
+            .map_err(|_| PixieError::MissingEndMarker)?;
+
+        if endmarker.manifest_offset >= marker_start {
+            return Err(PixieError::ManifestOutOfRange {
+                offset: endmarker.manifest_offset,
+                file_len: slice.len(),
+            });
+        }
+        Ok(&slice[endmarker.manifest_offset..marker_start])
+    }
+
+    /// Reads the manifest of a packed executable, checking that everything
+    /// it points to lies within `slice`.
+    pub fn read_from_full_slice(slice: &[u8]) -> Result<Self, PixieError> {
+        let (manifest, _) = Self::parse(Self::locate(slice)?)?;
+        manifest.stage2.check_bounds("stage2", slice.len())?;
+        for guest in &manifest.guests {
+            guest.resource.resource.check_bounds("guest", slice.len())?;
+        }
+        for embed in &manifest.embeds {
+            embed.resource.resource.check_bounds("embedded file", slice.len())?;
+        }
         Ok(manifest)
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GuestLayout;
+    use alloc::vec;
+
+    /// Size of the payload that comes before the manifest in `packed`
+    const PAYLOAD_LEN: usize = 64;
+
+    fn manifest(stage2: Resource) -> Manifest {
+        // all zeroes parse as a layout with empty pieces and no segments
+        let (_, layout) = GuestLayout::from_bytes((&[0; 60], 0)).unwrap();
+        Manifest {
+            stage2,
+            guests: vec![Guest {
+                name: ManifestString::new("guest").unwrap(),
+                encryption: Encryption::None,
+                resource: CompressedResource::new(Codec::Store, 0, &[], Some(&[][..])),
+                layout: Some(layout),
+                relocate: false,
+                rest_codec: Codec::Store,
+            }],
+            embeds: vec![],
+            signature: None,
+        }
+    }
+
+    /// Lays out a packed executable: a payload, the manifest, and the end
+    /// marker
+    fn packed(manifest: &Manifest) -> Vec<u8> {
+        let mut file = vec![0xaa; PAYLOAD_LEN];
+        file.extend(manifest.to_bytes().unwrap());
+        let marker = EndMarker {
+            manifest_offset: PAYLOAD_LEN,
+        };
+        file.extend(marker.to_bytes().unwrap());
+        file
+    }
+
+    fn stage2() -> Resource {
+        Resource {
+            offset: 0,
+            len: PAYLOAD_LEN,
+        }
+    }
+
+    #[test]
+    fn reads_manifest() {
+        let manifest = Manifest::read_from_full_slice(&packed(&manifest(stage2()))).unwrap();
+        assert_eq!(manifest.stage2.as_range(), 0..PAYLOAD_LEN);
+        assert_eq!(manifest.guests[0].name.as_str(), "guest");
+    }
+
+    #[test]
+    fn rejects_missing_end_marker() {
+        let file = packed(&manifest(stage2()));
+        let truncated = &file[..file.len() - 1];
+        let garbage = [0x5a; 256];
+        for slice in [&[][..], &file[..8], truncated, &garbage[..]] {
+            let res = Manifest::read_from_full_slice(slice);
+            assert!(matches!(res, Err(PixieError::MissingEndMarker)), "{:?}", res);
+        }
+    }
+
+    #[test]
+    fn rejects_manifest_offset_out_of_range() {
+        let mut file = packed(&manifest(stage2()));
+        let marker_start = file.len() - EndMarker::SIZE;
+        let marker = EndMarker {
+            manifest_offset: file.len(),
+        };
+        file.truncate(marker_start);
+        file.extend(marker.to_bytes().unwrap());
+        let res = Manifest::read_from_full_slice(&file);
+        assert!(matches!(res, Err(PixieError::ManifestOutOfRange { .. })), "{:?}", res);
+    }
+
+    #[test]
+    fn rejects_record_length_out_of_range() {
+        let mut file = packed(&manifest(stage2()));
+        // the first record's length, after the manifest header and its type
+        let len_start = PAYLOAD_LEN + 16 + 2;
+        file[len_start..len_start + 4].copy_from_slice(&[0xff; 4]);
+        let res = Manifest::read_from_full_slice(&file);
+        assert!(matches!(res, Err(PixieError::ManifestOutOfRange { .. })), "{:?}", res);
+    }
+
+    #[test]
+    fn rejects_garbage_manifest() {
+        let mut file = packed(&manifest(stage2()));
+        let marker_start = file.len() - EndMarker::SIZE;
+        file[PAYLOAD_LEN..marker_start].fill(0x5a);
+        assert!(Manifest::read_from_full_slice(&file).is_err());
+    }
+
+    #[test]
+    fn rejects_resource_out_of_range() {
+        let stage2 = Resource {
+            offset: PAYLOAD_LEN,
+            len: usize::MAX,
+        };
+        let res = Manifest::read_from_full_slice(&packed(&manifest(stage2)));
+        assert!(
+            matches!(res, Err(PixieError::ResourceOutOfRange { what: "stage2", .. })),
+            "{:?}",
+            res
+        );
+    }
+
+    #[test]
+    fn rejects_long_string() {
+        let long = "a".repeat(u16::MAX as usize + 1);
+        let res = ManifestString::new(&long);
+        assert!(matches!(res, Err(PixieError::StringTooLong(65536))), "{:?}", res);
+        assert!(ManifestString::new(&long[1..]).is_ok());
+    }
+
+    #[test]
+    fn names_guests_by_file_name() {
+        assert_eq!(guest_name("/usr/bin/git"), "git");
+        assert_eq!(guest_name("./tools.pak"), "tools.pak");
+        assert_eq!(guest_name("server"), "server");
+        assert_eq!(guest_name("dir/"), "");
+    }
+
+    /// Serializes a manifest header for `version`, followed by `records`
+    fn manifest_bytes(version: FormatVersion, records: Vec<Record>) -> Vec<u8> {
+        let header = ManifestHeader {
+            version,
+            record_count: records.len() as _,
+        };
+        let mut bytes = header.to_bytes().unwrap();
+        for record in &records {
+            bytes.extend(record.to_bytes().unwrap());
+        }
+        bytes
+    }
+
+    #[test]
+    fn reads_legacy_guest() {
+        let guest = CompressedResource::new(Codec::Lz4, 0, &[1, 2, 3], Some(&[4, 5, 6][..]));
+        let records = vec![
+            Record::new(RecordType::Stage2, stage2().to_bytes().unwrap()),
+            Record::new(RecordType::LegacyGuest, guest.to_bytes().unwrap()),
+        ];
+        let bytes = manifest_bytes(FormatVersion { major: 1, minor: 1 }, records);
+
+        let (manifest, _) = Manifest::parse(&bytes).unwrap();
+        assert_eq!(manifest.guests.len(), 1);
+        assert!(manifest.guests[0].layout.is_none());
+        assert!(matches!(manifest.guests[0].layout(), Err(PixieError::LegacyGuest)));
+    }
+
+    #[test]
+    fn reads_guest_from_before_relocate() {
+        let mut guest = manifest(stage2()).guests.remove(0);
+        guest.relocate = true;
+        let mut guest_bytes = guest.to_bytes().unwrap();
+        // 1.2 guests end right before the `relocate` flag, and the codec
+        // of the rest
+        guest_bytes.truncate(guest_bytes.len() - 2);
+        let records = vec![
+            Record::new(RecordType::Stage2, stage2().to_bytes().unwrap()),
+            Record::new(RecordType::Guest, guest_bytes),
+        ];
+        let bytes = manifest_bytes(FormatVersion { major: 1, minor: 2 }, records);
+
+        let (manifest, _) = Manifest::parse(&bytes).unwrap();
+        assert_eq!(manifest.guests[0].name.as_str(), "guest");
+        assert!(manifest.guests[0].relocate == false);
+    }
+
+    #[test]
+    fn rejects_other_major_versions() {
+        let records = vec![Record::new(RecordType::Stage2, stage2().to_bytes().unwrap())];
+        let bytes = manifest_bytes(FormatVersion { major: 2, minor: 0 }, records);
+        let res = Manifest::parse(&bytes);
+        assert!(matches!(res, Err(PixieError::UnsupportedManifestVersion(_))), "{:?}", res);
+    }
 }
\ No newline at end of file
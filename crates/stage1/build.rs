@@ -1,3 +1,40 @@
+use std::{
+    env,
+    fs,
+    path::PathBuf,
+};
+
 fn main() {
     println!("cargo:rustc-link-arg=-Wl,-z,defs");
+
+    embed_public_key();
+}
+
+/// Generates `public_key.rs`, which holds the Ed25519 public key packed
+/// executables must be signed with. It's taken from the `MINIPAK_PUBLIC_KEY`
+/// environment variable (64 hexadecimal digits). If it isn't set, unsigned
+/// executables are accepted.
+fn embed_public_key() {
+    println!("cargo:rerun-if-env-changed=MINIPAK_PUBLIC_KEY");
+
+    let key = match env::var("MINIPAK_PUBLIC_KEY") {
+        Ok(hex) => {
+            let hex = hex.trim();
+            if hex.len() != 64 || hex.bytes().all(|b| b.is_ascii_hexdigit()) == false {
+                panic!("MINIPAK_PUBLIC_KEY must be 64 hexadecimal digits, got {:?}", hex);
+            }
+            let bytes = (0..32)
+                .map(|i| format!("0x{}, ", &hex[i * 2..i * 2 + 2]))
+                .collect::<String>();
+            format!("Some([{}])", bytes)
+        }
+        Err(_) => "None".into(),
+    };
+
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("public_key.rs");
+    fs::write(
+        out_path,
+        format!("pub const PUBLIC_KEY: Option<[u8; 32]> = {};\n", key),
+    )
+    .unwrap();
 }
\ No newline at end of file
@@ -12,9 +12,20 @@ macro_rules! info {
     }
 }
 
+// Defines `PUBLIC_KEY`, see `build.rs`
+include!(concat!(env!("OUT_DIR"), "/public_key.rs"));
+
+/// Prints a diagnostic to stderr and exits with the error's exit code,
+/// without ever mapping stage2.
+fn bail(err: pixie::PixieError) -> ! {
+    eprintln!("[stage1] fatal: {}", err);
+    syscall::exit(err.exit_code());
+}
+
 /// # Safety
 /// Uses inline assembly so it can behave as the entry point of a static
 /// executable.
+#[cfg(target_arch = "x86_64")]
 #[no_mangle]
 #[naked]
 pub unsafe extern "C" fn entry() {
@@ -22,6 +33,33 @@ pub unsafe extern "C" fn entry() {
     asm!("mov rdi, rsp", "call premain", options(noreturn))
 }
 
+/// # Safety
+/// See the x86_64 version. The argument goes on the stack, which must still
+/// be 16-byte aligned at the call.
+#[cfg(target_arch = "x86")]
+#[no_mangle]
+#[naked]
+pub unsafe extern "C" fn entry() {
+    use core::arch::asm;
+    asm!(
+        "mov eax, esp",
+        "sub esp, 12",
+        "push eax",
+        "call premain",
+        options(noreturn)
+    )
+}
+
+/// # Safety
+/// See the x86_64 version.
+#[cfg(target_arch = "aarch64")]
+#[no_mangle]
+#[naked]
+pub unsafe extern "C" fn entry() {
+    use core::arch::asm;
+    asm!("mov x0, sp", "bl premain", options(noreturn))
+}
+
 /// # Safety
 /// Initializes the allocator.
 #[no_mangle]
@@ -37,26 +75,48 @@ unsafe fn premain(stack_top: *mut u8) -> ! {
 unsafe fn main(stack_top: *mut u8) -> ! {
     info!("Stack top: {:?}", stack_top);
 
-    let file = File::open("/proc/self/exe").unwrap();
-    let map = file.map().unwrap();
+    let file = File::open("/proc/self/exe").unwrap_or_else(|e| bail(e.into()));
+    let map = file.map().unwrap_or_else(|e| bail(e.into()));
     let full_slice = map.as_ref();
-    let manifest = pixie::Manifest::read_from_full_slice(full_slice).unwrap();
+    let manifest =
+        pixie::Manifest::read_from_full_slice(full_slice).unwrap_or_else(|e| bail(e));
+
+    // The signature covers stage2 as well as the guest, so check it before
+    // running any of it.
+    if let Some(public_key) = PUBLIC_KEY {
+        info!("Verifying signature...");
+        manifest
+            .verify_signature(full_slice, &public_key)
+            .unwrap_or_else(|e| bail(e));
+    }
 
-    let stage2_slice = &full_slice[manifest.stage2.as_range()];
-    let stage2_obj = pixie::Object::new(stage2_slice).unwrap();
-    let mut stage2_mapped = pixie::MappedObject::new(&stage2_obj, None).unwrap();
+    let stage2_slice = manifest
+        .stage2
+        .slice("stage2", full_slice)
+        .unwrap_or_else(|e| bail(e));
+    let stage2_obj = pixie::Object::new(stage2_slice).unwrap_or_else(|e| bail(e));
+    let mut stage2_mapped =
+        pixie::MappedObject::new(&stage2_obj, None).unwrap_or_else(|e| bail(e));
     info!(
         "Mapped stage2 at base 0x{:x} (offset 0x{:x})",
         stage2_mapped.base(),
         stage2_mapped.base_offset(),
     );
     info!("Relocating stage2...");
-    stage2_mapped.relocate(stage2_mapped.base_offset()).unwrap();
+    stage2_mapped
+        .relocate(stage2_mapped.base_offset())
+        .unwrap_or_else(|e| bail(e));
     info!("Relocating stage2 done!");
+    stage2_mapped.init_tls().unwrap_or_else(|e| bail(e));
+    stage2_mapped.protect().unwrap_or_else(|e| bail(e));
+    stage2_mapped.protect_relro().unwrap_or_else(|e| bail(e));
 
-    let s2_entry = stage2_mapped.lookup_sym("entry").unwrap();
+    let s2_entry = stage2_mapped
+        .lookup_sym("entry")
+        .unwrap_or_else(|e| bail(e));
     info!("Found entry sym {:?}", s2_entry);
-    let entry: unsafe extern "C" fn(*mut u8) -> ! = 
-        core::mem::transmute(stage2_mapped.base_offset() + s2_entry.value);
-    entry(stack_top);
+    let entry: unsafe extern "C" fn(*mut u8, pixie::PackedExe) -> ! =
+        core::mem::transmute((stage2_mapped.base_offset() + s2_entry.value) as usize);
+    // never returns, so `map` stays around for stage2
+    entry(stack_top, pixie::PackedExe::new(file.fd(), full_slice));
 }
\ No newline at end of file
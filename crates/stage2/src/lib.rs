@@ -4,12 +4,18 @@
 
 extern crate alloc;
 
-use encore::prelude::*;
+use encore::{
+    memmap::FileOpts,
+    prelude::*,
+};
 use pixie::{
+    ElfClass,
     Manifest,
     MappedObject,
     Object,
     ObjectHeader,
+    PackedExe,
+    PixieError,
 };
 
 macro_rules! info {
@@ -18,39 +24,90 @@ macro_rules! info {
     }
 }
 
+/// Prints a diagnostic to stderr and exits with the error's exit code,
+/// without ever jumping to the guest.
+fn bail(err: PixieError) -> ! {
+    eprintln!("[stage2] fatal: {}", err);
+    syscall::exit(err.exit_code());
+}
+
+/// Writes every embedded file to a memory file, and points its environment
+/// variable to it (see `pixie::embed_var_name`). The guest inherits the file
+/// descriptors.
+fn extract_embeds(
+    manifest: &Manifest,
+    full_slice: &[u8],
+    stack: &mut Env,
+) -> Result<(), PixieError> {
+    for embed in &manifest.embeds {
+        let contents = embed.load(full_slice, stack)?;
+        let mut file = File::memfd(embed.name.as_str())?;
+        file.write_all(&contents)?;
+
+        info!("Extracted {} to {}", embed.name.as_str(), file.path());
+        stack.set_var(&pixie::embed_var_name(embed.name.as_str()), file.path());
+        // keep it open for the guest
+        file.into_fd();
+    }
+    Ok(())
+}
+
+/// Jumps to `entry_point`, handing over the environment variables we set,
+/// if any.
+///
+/// # Safety
+/// See `pixie::launch`
+unsafe fn launch(stack_top: *mut u8, entry_point: u64, stack: &Env, env_changed: bool) -> ! {
+    if env_changed {
+        pixie::launch_with_env(stack_top, entry_point, stack)
+    } else {
+        pixie::launch(stack_top, entry_point)
+    }
+}
+
 /// # Safety
 /// Does a raw syscall, initializes the global allocator
 #[no_mangle]
 #[inline(never)]
-pub unsafe extern "C" fn entry(stack_top: *mut u8) -> ! {
+pub unsafe extern "C" fn entry(stack_top: *mut u8, exe: PackedExe) -> ! {
     init_allocator();
-    crate::main(stack_top);
+    crate::main(stack_top, exe);
 }
 
 /// # Safety
 /// Maps and jumps to another ELF object
 #[inline(never)]
-unsafe fn main(stack_top: *mut u8) -> ! {
+unsafe fn main(stack_top: *mut u8, exe: PackedExe) -> ! {
     info!("Stack top: {:?}", stack_top);
 
     let mut stack = Env::read(stack_top as _);
 
-    let file = File::open("/proc/self/exe").unwrap();
-    let map = file.map().unwrap();
-    let full_slice = map.as_ref();
-    let manifest = Manifest::read_from_full_slice(full_slice).unwrap();
-
-    info!("Decompressing guest...");
-    let compressed_guest = &full_slice[manifest.guest.as_range()];
-    let guest = lz4_flex::decompress_size_prepended(compressed_guest).unwrap();
-    let guest_obj = Object::new(guest.as_ref()).unwrap();
-    info!("Guest decompressed.");
-
-    let guest_hull = guest_obj.segments().load_convex_hull().unwrap();
+    // What stage1 checked the signature of, if it had to
+    let full_slice = exe.full_slice();
+    let manifest = Manifest::read_from_full_slice(full_slice).unwrap_or_else(|e| bail(e));
+
+    let guest = manifest.select_guest(&stack).unwrap_or_else(|e| bail(e));
+    let codec = guest.resource.codec;
+    info!("Loading guest {} ({})...", guest.name.as_str(), codec.name());
+    let layout = guest.layout().unwrap_or_else(|e| bail(e));
+    let pieces = guest.pieces_to_map(full_slice, &stack).unwrap_or_else(|e| bail(e));
+    let headers = layout
+        .headers
+        .decompress(codec, &pieces)
+        .unwrap_or_else(|e| bail(e));
+    let guest_obj = Object::from_headers(&headers).unwrap_or_else(|e| bail(e));
+
+    extract_embeds(&manifest, full_slice, &mut stack).unwrap_or_else(|e| bail(e));
+    let env_changed = manifest.embeds.is_empty() == false;
+
+    let guest_hull = guest_obj
+        .segments()
+        .load_convex_hull()
+        .unwrap_or_else(|e| bail(e));
     let at = if guest_hull.start == 0 {
         // guest is relocatable, load it with the same base as ourselves
-        let elf_header_address = stack.find_vector(AuxvType::PHDR).value;
-        let self_base = elf_header_address - ObjectHeader::SIZE as u64;
+        let elf_header_address = stack.find_vector(AuxvType::PHDR).value as u64;
+        let self_base = elf_header_address - ObjectHeader::size(ElfClass::NATIVE) as u64;
         Some(self_base)
     } else {
         // guest is non-relocatable, it'll be loaded at its preferred offset
@@ -58,47 +115,83 @@ unsafe fn main(stack_top: *mut u8) -> ! {
     };
     let base_offset = at.unwrap_or_default();
 
-    let guest_mapped = MappedObject::new(&guest_obj, at).unwrap();
-    info!("Mapping guest at 0x{:x}", guest_mapped.base());
+    // Load segments are decompressed straight into the guest's mapping,
+    // the rest of the guest is never decompressed. Stored segments are
+    // checked, then mapped from our own file instead, so concurrent
+    // instances share them.
+    let mut guest_mapped = MappedObject::map_with(&guest_obj, at, |i, _, dst| {
+        let piece = layout.segments.get(i).ok_or(PixieError::GuestLayoutMismatch)?;
+        if let Some(offset) = guest.segment_file_offset(&guest_obj, i) {
+            piece.check_stored(&pieces)?;
+            return Ok(Some(FileOpts { fd: exe.fd(), offset }));
+        }
+        piece.decompress_into(codec, &pieces, dst)?;
+        Ok(None)
+    })
+    .unwrap_or_else(|e| bail(e));
+    drop(pieces);
+    info!("Mapped guest at 0x{:x}", guest_mapped.base());
+    // Unless asked to, leave the guest's relocations to its interpreter or
+    // to the guest itself. Either way, `GNU_RELRO` stays writable: a
+    // static-pie guest applies its own relocations again, then protects it.
+    if guest.relocate {
+        info!("Relocating guest...");
+        guest_mapped.set_hwcaps(&stack);
+        guest_mapped
+            .relocate(guest_mapped.base_offset())
+            .unwrap_or_else(|e| bail(e));
+    }
+    guest_mapped.protect().unwrap_or_else(|e| bail(e));
 
     // set aux vectors
     let at_phdr = stack.find_vector(AuxvType::PHDR);
-    at_phdr.value = guest_mapped.base() + guest_obj.header().ph_offset;
+    at_phdr.value = (guest_mapped.base() + guest_obj.header().ph_offset) as _;
 
     let at_phnum = stack.find_vector(AuxvType::PHNUM);
     at_phnum.value = guest_obj.header().ph_count as _;
 
     let at_entry = stack.find_vector(AuxvType::ENTRY);
-    at_entry.value = base_offset + guest_obj.header().entry_point;
+    at_entry.value = (base_offset + guest_obj.header().entry_point) as _;
 
     match guest_obj.segments().find(pixie::SegmentType::Interp) {
         Ok(interp) => {
-            let interp = core::str::from_utf8(interp.slice()).unwrap();
+            // We only have the guest's headers, but the interpreter's path
+            // is in a load segment, which we've mapped.
+            let interp = guest_mapped
+                .vaddr_slice(interp.header().mem_range())
+                .unwrap_or_else(|e| bail(e));
+            let interp = core::str::from_utf8(interp)
+                .unwrap_or_else(|_| bail(PixieError::InvalidInterpreterPath));
             info!("Should load interpreter {}!", interp);
 
-            let interp_file = File::open(interp).unwrap();
-            let interp_map = interp_file.map().unwrap();
-            let interp_obj = Object::new(interp_map.as_ref()).unwrap();
-            let interp_hull = interp_obj.segments().load_convex_hull().unwrap();
+            let interp_file = File::open(interp).unwrap_or_else(|e| bail(e.into()));
+            let interp_map = interp_file.map().unwrap_or_else(|e| bail(e.into()));
+            let interp_obj = Object::new(interp_map.as_ref()).unwrap_or_else(|e| bail(e));
+            let interp_hull = interp_obj
+                .segments()
+                .load_convex_hull()
+                .unwrap_or_else(|e| bail(e));
             if interp_hull.start != 0 {
-                panic!("Expected interpreter to be relocatable");
+                bail(PixieError::InterpreterNotRelocatable(interp.into()));
             }
 
             // Map interpreter anywhere
-            let interp_mapped = MappedObject::new(&interp_obj, None).unwrap();
+            let interp_mapped =
+                MappedObject::new(&interp_obj, None).unwrap_or_else(|e| bail(e));
+            interp_mapped.protect().unwrap_or_else(|e| bail(e));
 
             // Adjust base
             let at_base = stack.find_vector(AuxvType::BASE);
-            at_base.value = interp_mapped.base();
+            at_base.value = interp_mapped.base() as _;
 
             let entry_point = interp_mapped.base() + interp_obj.header().entry_point;
             info!("Jumping to interpreter's entry point 0x{:x}", entry_point);
-            pixie::launch(stack_top, entry_point);
+            launch(stack_top, entry_point, &stack, env_changed);
         }
         Err(_) => {
             let entry_point = base_offset + guest_obj.header().entry_point;
             info!("Jumping to guest's entry point 0x{:x}", entry_point);
-            pixie::launch(stack_top, entry_point);
+            launch(stack_top, entry_point, &stack, env_changed);
         }
     }
 }
\ No newline at end of file